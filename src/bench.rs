@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+use crate::compiler::compiler::Compiler;
+use crate::parser::parser::parse_from_program;
+use crate::vm::vm::VM;
+
+/*
+ * Small programs that lean on particular parts of the VM, run with `ysetl --bench`. Each one is
+ * compiled and then executed repeatedly, and only the time spent in the VM is measured. Iterator
+ * formers like `{x * 2 : x in s}` aren't compiled yet, so `set_ranges` builds sets from ranges.
+ */
+
+const BENCHMARKS: &[(&str, &str)] = &[
+    (
+        "int_arith",
+        "program :bench;
+        loop = 0;
+        loop = func(n, acc) { if n == 0 ? acc : loop(n - 1, acc + n * 3 - n div 2) };
+        loop(100000, 0);",
+    ),
+    (
+        "float_arith",
+        "program :bench;
+        loop = 0;
+        loop = func(n, acc) { if n == 0 ? acc : loop(n - 1, acc * 0.5 + n / 4.0) };
+        loop(100000, 0.0);",
+    ),
+    (
+        "compare",
+        "program :bench;
+        loop = 0;
+        loop = func(n, hits) { if n == 0 ? hits : loop(n - 1, case { n < 500 : hits + 1, n == 900 : hits + 1, ~ : hits }) };
+        loop(100000, 0);",
+    ),
+    (
+        "fib",
+        "program :bench;
+        fib = 0;
+        fib = func(n) { if n < 2 ? n : fib(n - 1) + fib(n - 2) };
+        fib(22);",
+    ),
+    (
+        "locals",
+        "program :bench;
+        step = func(n) { a = n; b = a + 1; c = b + a; d = c - b; d };
+        loop = 0;
+        loop = func(n) { if n == 0 ? 0 : loop(step(n) - 1) };
+        loop(50000);",
+    ),
+    (
+        "ranges",
+        "program :bench;
+        loop = 0;
+        loop = func(n, acc) { if n == 0 ? acc : loop(n - 1, acc + [1..1000][n - 1]) };
+        loop(1000, 0);",
+    ),
+    (
+        "set_build",
+        "program :bench;
+        build = 0;
+        build = func(n, s) { if n == 0 ? s : build(n - 1, s with n) };
+        build(20000, {});",
+    ),
+    (
+        "set_ranges",
+        "program :bench;
+        build = 0;
+        build = func(i, acc) { if i == 0 ? acc : build(i - 1, acc union {i * 10..i * 10 + 20} union {i, i + 2..i + 40}) };
+        build(500, {});",
+    ),
+    (
+        "sieve",
+        "program :bench;
+        strike = 0;
+        strike = func(m, step, n, flags) {
+            case { n < m : flags, ~ : { flags[m] = false; strike(m + step, step, n, flags) } }
+        };
+        sieve = 0;
+        sieve = func(p, n, flags) {
+            case {
+                n < p * p : flags,
+                flags[p] : sieve(p + 1, n, strike(p * p, p, n, flags)),
+                ~ : sieve(p + 1, n, flags)
+            }
+        };
+        count = 0;
+        count = func(i, n, flags, total) {
+            if n < i ? total : count(i + 1, n, flags, if flags[i] ? total + 1 : total)
+        };
+        count(2, 20000, sieve(2, 20000, [1..20001]), 0);",
+    ),
+    // The deep recursion programs from the VM's tests
+    (
+        "tail_sum",
+        "program :bench;
+        sum = 0;
+        sum = func(n, acc) { if n == 0 ? acc : sum(n - 1, acc + n) };
+        sum(100000, 0);",
+    ),
+    (
+        "mutual_rec",
+        "program :bench;
+        is_even = 0; is_odd = 0;
+        is_even = func(n) { case { n == 0 : true, ~ : is_odd(n - 1) } };
+        is_odd = func(n) { case { n == 0 : false, ~ : is_even(n - 1) } };
+        is_even(100001);",
+    ),
+];
+
+const MIN_RUNS: u32 = 5;
+const MIN_TIME: Duration = Duration::from_millis(500);
+
+pub fn run_benchmarks(optimize: bool) {
+    println!("{:<12} {:>6} {:>12} {:>12} {:>12}", "benchmark", "runs", "mean", "instructions", "Minstr/s");
+    for (name, source) in BENCHMARKS {
+        let mut runs = 0;
+        let mut total = Duration::ZERO;
+        let mut instructions = 0;
+        while runs < MIN_RUNS || total < MIN_TIME {
+            let mut compiler = Compiler::with_optimizations(optimize);
+            compiler.compile_program(parse_from_program(source).unwrap());
+            let mut vm = VM::new(compiler.finish());
+            let start = Instant::now();
+            vm.run().unwrap();
+            total += start.elapsed();
+            instructions += vm.instruction_count();
+            runs += 1;
+        }
+        println!(
+            "{:<12} {:>6} {:>10.3}ms {:>12} {:>12.1}",
+            name,
+            runs,
+            total.as_secs_f64() * 1000.0 / runs as f64,
+            instructions / runs as u64,
+            instructions as f64 / total.as_secs_f64() / 1_000_000.0,
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::code::is_jump;
+use super::debug::lookup;
+use super::words::Code;
+use crate::compiler::compiler::Bytecode;
+use crate::object::object::BaseObject;
+
+/*
+ * Reads the same text format that the disassembler writes (see `code::disassembler`). Offsets
+ * in front of instructions (`  12: Add`) and comments after a `;` are ignored, so disassembled
+ * output can be fed straight back in. Jump operands can be any label defined in the same
+ * `.code` section, with labels written on their own line as `name:`. A numeric jump operand has to
+ * land on the start of an instruction, or on the end of the section.
+ */
+
+type AsmResult<T> = Result<T, String>;
+
+enum Operand<'a> {
+    Number(usize),
+    Label(&'a str),
+}
+
+enum Item<'a> {
+    Label(&'a str),
+    Op {
+        op: u8,
+        operands: Vec<Operand<'a>>,
+        line: usize,
+    },
+}
+
+struct Section<'a> {
+    // `None` is the main program, otherwise it's the constant index of the function
+    target: Option<usize>,
+    items: Vec<Item<'a>>,
+}
+
+/** Assembles YSETL assembly text into bytecode that the VM can run */
+pub fn assemble(source: &str) -> AsmResult<Bytecode> {
+    let mnemonics: HashMap<&'static str, u8> = (0..=u8::MAX)
+        .filter_map(|byte| lookup(byte).map(|(_, name)| (name, byte)))
+        .collect();
+
+    let mut constants: Vec<BaseObject> = vec![];
+    let mut global_names: Vec<String> = vec![];
+    let mut sections: Vec<Section> = vec![];
+
+    for (line_no, raw_line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let err = |msg: String| format!("line {}: {}", line_no, msg);
+
+        if let Some(rest) = line.strip_prefix(".const ") {
+            let (index, decl) = split_index(rest).map_err(err)?;
+            if index != constants.len() {
+                return Err(err(format!(
+                    "expected constant {} next, found {}",
+                    constants.len(),
+                    index
+                )));
+            }
+            constants.push(parse_const(decl).map_err(err)?);
+        } else if let Some(rest) = line.strip_prefix(".global ") {
+            let (index, name) = split_index(rest).map_err(err)?;
+            if index != global_names.len() {
+                return Err(err(format!(
+                    "expected global {} next, found {}",
+                    global_names.len(),
+                    index
+                )));
+            }
+            global_names.push(name.to_owned());
+        } else if let Some(rest) = line.strip_prefix(".code ") {
+            let target = match rest.trim() {
+                "main" => None,
+                index => Some(parse_number(index).map_err(err)?),
+            };
+            if sections.iter().any(|s| s.target == target) {
+                return Err(err(format!("duplicate section for .code {}", rest.trim())));
+            }
+            sections.push(Section {
+                target,
+                items: vec![],
+            });
+        } else {
+            let section = sections
+                .last_mut()
+                .ok_or_else(|| err("instruction found before any .code section".to_owned()))?;
+            let code_part = line.split(';').next().unwrap();
+            parse_code_line(code_part, line_no, &mnemonics, &mut section.items).map_err(err)?;
+        }
+    }
+
+    let mut main_ins: Option<Bytes> = None;
+    for section in sections.iter() {
+        let ins = encode_section(section)?;
+        match section.target {
+            None => main_ins = Some(ins),
+            Some(index) => match constants.get_mut(index) {
+                Some(BaseObject::Function { ins: fn_ins, .. }) => *fn_ins = Rc::new(Code::new(ins)),
+                _ => return Err(format!(".code {} does not refer to a function constant", index)),
+            },
+        }
+    }
+
+    for (index, constant) in constants.iter().enumerate() {
+        if let BaseObject::Function { ins, .. } = constant {
+            if !sections.iter().any(|s| s.target == Some(index)) && ins.bytes().is_empty() {
+                return Err(format!("function constant {} has no .code section", index));
+            }
+        }
+    }
+
+    Ok(Bytecode {
+        instuctions: main_ins.ok_or("missing .code main section")?,
+        constants,
+        global_count: global_names.len(),
+        global_names,
+        natives: vec![],
+    })
+}
+
+fn parse_number(input: &str) -> AsmResult<usize> {
+    input
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a number, found '{}'", input.trim()))
+}
+
+fn split_index(input: &str) -> AsmResult<(usize, &str)> {
+    let input = input.trim_start();
+    let (index, rest) = input.split_once(' ').unwrap_or((input, ""));
+    Ok((parse_number(index)?, rest.trim()))
+}
+
+fn parse_const(decl: &str) -> AsmResult<BaseObject> {
+    let (kind, value) = decl.split_once(' ').unwrap_or((decl, ""));
+    match kind {
+        "null" => Ok(BaseObject::Null),
+        "true" => Ok(BaseObject::True),
+        "false" => Ok(BaseObject::False),
+        "int" => value
+            .trim()
+            .parse()
+            .map(BaseObject::Integer)
+            .map_err(|_| format!("invalid int '{}'", value.trim())),
+        "float" => value
+            .trim()
+            .parse()
+            .map(BaseObject::Float)
+            .map_err(|_| format!("invalid float '{}'", value.trim())),
+        "str" => parse_string(value.trim()).map(|str| BaseObject::String(str.into())),
+        "func" => {
+            let mut req_params = None;
+            let mut opt_params = None;
+            let mut locals = None;
+            for field in value.split_whitespace() {
+                let (key, val) = field
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected key=value, found '{}'", field))?;
+                let val = parse_number(val)?;
+                match key {
+                    "req" => req_params = Some(val as u16),
+                    "opt" => opt_params = Some(val as u16),
+                    "locals" => locals = Some(val),
+                    other => return Err(format!("unknown function field '{}'", other)),
+                }
+            }
+            Ok(BaseObject::Function {
+                ins: Rc::new(Code::new(Bytes::new())),
+                locals: locals.ok_or("function constant is missing locals=")?,
+                req_params: req_params.ok_or("function constant is missing req=")?,
+                opt_params: opt_params.ok_or("function constant is missing opt=")?,
+                locked_values: vec![],
+            })
+        }
+        other => Err(format!("unknown constant type '{}'", other)),
+    }
+}
+
+/** Reads a double-quoted string with the same escapes that Rust's `Debug` output produces */
+fn parse_string(input: &str) -> AsmResult<String> {
+    let inner = input
+        .strip_prefix('"')
+        .ok_or_else(|| format!("expected a quoted string, found '{}'", input))?;
+
+    let mut output = String::new();
+    let mut chars = inner.chars();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_owned()),
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('n') => output.push('\n'),
+                Some('t') => output.push('\t'),
+                Some('r') => output.push('\r'),
+                Some('0') => output.push('\0'),
+                Some('\\') => output.push('\\'),
+                Some('"') => output.push('"'),
+                Some('\'') => output.push('\''),
+                Some('u') => {
+                    let hex: String = chars.by_ref().skip(1).take_while(|ch| *ch != '}').collect();
+                    let ch = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid unicode escape '{}'", hex))?;
+                    output.push(ch);
+                }
+                other => return Err(format!("unknown escape {:?}", other)),
+            },
+            Some(ch) => output.push(ch),
+        }
+    }
+
+    if !chars.as_str().trim().is_empty() {
+        return Err(format!("unexpected text after string: '{}'", chars.as_str()));
+    }
+    Ok(output)
+}
+
+fn parse_code_line<'a>(
+    line: &'a str,
+    line_no: usize,
+    mnemonics: &HashMap<&'static str, u8>,
+    items: &mut Vec<Item<'a>>,
+) -> AsmResult<()> {
+    let mut tokens = line.split_whitespace().peekable();
+
+    // Any number of offsets or labels can prefix the instruction
+    while let Some(prefix) = tokens.peek().and_then(|token| token.strip_suffix(':')) {
+        tokens.next();
+        if !prefix.chars().all(|ch| ch.is_ascii_digit()) {
+            items.push(Item::Label(prefix));
+        }
+    }
+
+    let Some(mnemonic) = tokens.next() else {
+        return Ok(());
+    };
+    let op = *mnemonics
+        .get(mnemonic)
+        .ok_or_else(|| format!("unknown instruction '{}'", mnemonic))?;
+
+    let operands = tokens
+        .map(|token| match token.parse() {
+            Ok(number) => Ok(Operand::Number(number)),
+            Err(_) if is_jump(op) => Ok(Operand::Label(token)),
+            Err(_) => Err(format!("invalid operand '{}' for {}", token, mnemonic)),
+        })
+        .collect::<AsmResult<Vec<_>>>()?;
+
+    let expected = lookup(op).unwrap().0.len();
+    if operands.len() != expected {
+        return Err(format!(
+            "{} takes {} operand(s), found {}",
+            mnemonic,
+            expected,
+            operands.len()
+        ));
+    }
+
+    items.push(Item::Op {
+        op,
+        operands,
+        line: line_no,
+    });
+    Ok(())
+}
+
+fn encode_section(section: &Section) -> AsmResult<Bytes> {
+    // First pass finds where every label and instruction lands
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut starts: HashSet<usize> = HashSet::new();
+    let mut pos = 0;
+    for item in section.items.iter() {
+        match item {
+            Item::Label(name) => {
+                if labels.insert(name, pos).is_some() {
+                    return Err(format!("label '{}' is defined more than once", name));
+                }
+            }
+            Item::Op { op, .. } => {
+                starts.insert(pos);
+                pos += 1 + lookup(*op).unwrap().0.iter().sum::<usize>();
+            }
+        }
+    }
+    // Jumping to the very end finishes the section
+    starts.insert(pos);
+
+    let mut bytes = BytesMut::with_capacity(pos);
+    for item in section.items.iter() {
+        let Item::Op { op, operands, line } = item else {
+            continue;
+        };
+        bytes.put_u8(*op);
+        for (size, operand) in lookup(*op).unwrap().0.iter().zip(operands.iter()) {
+            let value = match operand {
+                Operand::Number(number) => *number,
+                Operand::Label(name) => *labels
+                    .get(name)
+                    .ok_or_else(|| format!("line {}: undefined label '{}'", line, name))?,
+            };
+            if is_jump(*op) && !starts.contains(&value) {
+                return Err(format!("line {}: jump to {} is not the start of an instruction", line, value));
+            }
+            match *size {
+                2 => {
+                    let value = u16::try_from(value)
+                        .map_err(|_| format!("line {}: operand {} does not fit in u16", line, value))?;
+                    bytes.put_u16(value);
+                }
+                4 => {
+                    let value = u32::try_from(value)
+                        .map_err(|_| format!("line {}: operand {} does not fit in u32", line, value))?;
+                    bytes.put_u32(value);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    Ok(bytes.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::code::code::{self, OpCode};
+    use crate::code::disassembler::disassemble;
+    use crate::compiler::compiler::Compiler;
+    use crate::object::object::BaseObject::*;
+    use crate::parser::parser;
+
+    #[test] #[rustfmt::skip]
+    fn labels_and_constants() {
+        let bytecode = assemble(
+            r#"
+            .const 0 int 7
+            .const 1 str "a\"b\n"
+            .global 0 x
+
+            .code main
+            start:
+                Const 0
+                JumpNotTrue end
+                Jump start
+            end:
+                SetGVar 0 ; x
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(bytecode.constants, vec![Integer(7), String("a\"b\n".into())]);
+        assert_eq!(bytecode.global_names, vec!["x".to_owned()]);
+        assert_eq!(
+            bytecode.instuctions[..],
+            [
+                code::Const::VAL, 0, 0,
+                code::JumpNotTrue::VAL, 0, 9,
+                code::Jump::VAL, 0, 0,
+                code::SetGVar::VAL, 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(assemble("Pop").unwrap_err().contains("before any .code section"));
+        assert!(assemble(".code main\nFoo").unwrap_err().contains("unknown instruction 'Foo'"));
+        assert!(assemble(".code main\nJump nowhere").unwrap_err().contains("undefined label"));
+        assert!(assemble(".code main\nConst").unwrap_err().contains("takes 1 operand(s)"));
+        assert_eq!(
+            assemble(".const 0 int 1\n.code main\nConst 0\nJump 1").unwrap_err(),
+            "line 4: jump to 1 is not the start of an instruction"
+        );
+        assert!(assemble(".code main\nJump 4").is_err());
+        assert!(assemble(".code main\nJump 3").is_ok());
+        assert!(assemble(".const 0 func req=0 opt=0 locals=0\n.code main\nPop")
+            .unwrap_err()
+            .contains("has no .code section"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = r#"program :round_trip;
+            c = 100;
+            label = "tab\there";
+            bar = func (a, b?, c!) {
+                g = -99.5;
+                case (a) {
+                    1 : g,
+                    ~ : a + (if b ? g : c)
+                }
+            };
+            bar(1, false);
+        "#;
+        let mut c = Compiler::new();
+        c.compile_program(parser::parse_from_program(input).unwrap());
+        let original = c.finish();
+
+        let text = disassemble(&original);
+        let assembled = assemble(&text).unwrap();
+
+        assert_eq!(assembled.instuctions, original.instuctions);
+        assert_eq!(assembled.constants, original.constants);
+        assert_eq!(assembled.global_names, original.global_names);
+        assert_eq!(assembled.global_count, original.global_count);
+        assert_eq!(disassemble(&assembled), text);
+    }
+}
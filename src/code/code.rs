@@ -14,17 +14,43 @@ ToSet        |   9
 ToTupleRn    |  10
 ToSetRn      |  11
 ToFn         |  12
+Const32      |  13
+GetLVar0     |  14
+GetLVar1     |  15
+GetLVar2     |  16
+GetLVar3     |  17
+IncLocal     |  18
+Line         |  19
 Pop          |  20
 PushMatch    |  21
 PopMatch     |  22
 Jump         |  23
 JumpNotTrue  |  24
 JumpNotMatch |  25
+JumpW        |  26
+JumpNotTrueW |  27
+JumpNotMatchW|  28
+JumpTrue     |  29
+JumpTrueW    |  30
+LtJumpNotTrue|  31
+LtJumpNotTrueW| 32
+Concat       |  33
+TakeLVar     |  34
 Return       |  50
+ReturnNull   |  51
 Index        | 100
 Range        | 101
 Pick         | 102
 Call         | 103
+TailCall     | 104
+SetIndex     | 105
+SetIndexGVar | 106
+SetIndexLVar | 107
+WithGVar     | 108
+WithLVar     | 109
+LessGVar     | 110
+LessLVar     | 111
+SetIndexPath | 112
 NullCoal     | 200
 TupleStart   | 201
 Exp          | 202
@@ -53,6 +79,7 @@ Negate       | 226
 DynVar       | 227
 Size         | 228
 Not          | 229
+AddConst     | 230
 */
 
 pub trait OpCodeMake {
@@ -65,6 +92,11 @@ pub trait OpCodeMakeWithU16 {
     const OPERAND_COUNTS: &'static [usize] = &[2];
 }
 
+pub trait OpCodeMakeWithU32 {
+    fn make(self, operand: u32) -> Bytes;
+    const OPERAND_COUNTS: &'static [usize] = &[4];
+}
+
 /** Used to implement all opcodes to give them a value */
 pub trait OpCode {
     const VAL: u8;
@@ -76,6 +108,9 @@ pub trait OpCodeNone {}
 /** Used to implement opcodes which take a single 2-byte operand */
 pub trait OpCodeU16 {}
 
+/** Used to implement opcodes which take a single 4-byte operand */
+pub trait OpCodeU32 {}
+
 impl<T: Sized + OpCode + OpCodeNone> OpCodeMake for T {
     fn make(self) -> Bytes {
         let mut bytes = BytesMut::with_capacity(1);
@@ -93,6 +128,49 @@ impl<T: Sized + OpCode + OpCodeU16> OpCodeMakeWithU16 for T {
     }
 }
 
+/** Whether the opcode's operand is an instruction offset within the same function */
+pub fn is_jump(op: u8) -> bool {
+    matches!(
+        op,
+        Jump::VAL
+            | JumpNotTrue::VAL
+            | JumpNotMatch::VAL
+            | JumpW::VAL
+            | JumpNotTrueW::VAL
+            | JumpNotMatchW::VAL
+            | JumpTrue::VAL
+            | JumpTrueW::VAL
+            | LtJumpNotTrue::VAL
+            | LtJumpNotTrueW::VAL
+    )
+}
+
+/** Pairs of each jump with its wide counterpart, for targets past the reach of a u16 */
+pub const JUMP_WIDTHS: [(u8, u8); 5] = [
+    (Jump::VAL, JumpW::VAL),
+    (JumpNotTrue::VAL, JumpNotTrueW::VAL),
+    (JumpNotMatch::VAL, JumpNotMatchW::VAL),
+    (JumpTrue::VAL, JumpTrueW::VAL),
+    (LtJumpNotTrue::VAL, LtJumpNotTrueW::VAL),
+];
+
+/** The narrow form of a wide jump. Any other opcode is returned as is. */
+pub fn narrow_jump(op: u8) -> u8 {
+    JUMP_WIDTHS
+        .iter()
+        .find(|(_, wide)| *wide == op)
+        .map_or(op, |(narrow, _)| *narrow)
+}
+
+impl<T: Sized + OpCode + OpCodeU32> OpCodeMakeWithU32 for T {
+    fn make(self, operand: u32) -> Bytes {
+        let mut bytes = BytesMut::with_capacity(5);
+        bytes.put_u8(Self::VAL);
+        bytes.put_u32(operand);
+        bytes.freeze()
+    }
+}
+
 #[derive(Debug)]
 pub struct Const;
 impl OpCodeU16 for Const {}
@@ -149,6 +227,59 @@ impl OpCode for GetLVar {
     const VAL: u8 = 7;
 }
 
+/*
+ * Specialized forms of common instruction sequences, substituted in once a function's code is
+ * complete (see `compiler::specialize`). They never appear in the compiler's own output.
+ */
+
+/** `GetLVar` for one of the first four locals, which are the most common */
+#[derive(Debug)]
+pub struct GetLVar0;
+impl OpCodeNone for GetLVar0 {}
+impl OpCode for GetLVar0 {
+    const VAL: u8 = 14;
+}
+
+#[derive(Debug)]
+pub struct GetLVar1;
+impl OpCodeNone for GetLVar1 {}
+impl OpCode for GetLVar1 {
+    const VAL: u8 = 15;
+}
+
+#[derive(Debug)]
+pub struct GetLVar2;
+impl OpCodeNone for GetLVar2 {}
+impl OpCode for GetLVar2 {
+    const VAL: u8 = 16;
+}
+
+#[derive(Debug)]
+pub struct GetLVar3;
+impl OpCodeNone for GetLVar3 {}
+impl OpCode for GetLVar3 {
+    const VAL: u8 = 17;
+}
+
+/** `x = x + 1` for a local, pushing the new value */
+#[derive(Debug)]
+pub struct IncLocal;
+impl OpCodeU16 for IncLocal {}
+impl OpCode for IncLocal {
+    const VAL: u8 = 18;
+}
+
+/**
+ * Marks where the statement on the given source line begins. It's only emitted when the compiler
+ * is asked for line info, and it's stripped out when code is decoded for the VM.
+ */
+#[derive(Debug)]
+pub struct Line;
+impl OpCodeU32 for Line {}
+impl OpCode for Line {
+    const VAL: u8 = 19;
+}
+
 #[derive(Debug)]
 pub struct ToTuple;
 impl OpCodeU16 for ToTuple {}
@@ -184,6 +315,14 @@ impl OpCode for ToFn {
     const VAL: u8 = 12;
 }
 
+/** Same as `Const`, for constant pools that grow past what a u16 can index */
+#[derive(Debug)]
+pub struct Const32;
+impl OpCodeU32 for Const32 {}
+impl OpCode for Const32 {
+    const VAL: u8 = 13;
+}
+
 #[derive(Debug)]
 pub struct Pop;
 impl OpCodeNone for Pop {}
@@ -226,6 +365,73 @@ impl OpCode for JumpNotMatch {
     const VAL: u8 = 25;
 }
 
+#[derive(Debug)]
+pub struct JumpW;
+impl OpCodeU32 for JumpW {}
+impl OpCode for JumpW {
+    const VAL: u8 = 26;
+}
+
+#[derive(Debug)]
+pub struct JumpNotTrueW;
+impl OpCodeU32 for JumpNotTrueW {}
+impl OpCode for JumpNotTrueW {
+    const VAL: u8 = 27;
+}
+
+#[derive(Debug)]
+pub struct JumpNotMatchW;
+impl OpCodeU32 for JumpNotMatchW {}
+impl OpCode for JumpNotMatchW {
+    const VAL: u8 = 28;
+}
+
+/** Inverse of `JumpNotTrue`, produced by the peephole pass in place of `Not, JumpNotTrue` */
+#[derive(Debug)]
+pub struct JumpTrue;
+impl OpCodeU16 for JumpTrue {}
+impl OpCode for JumpTrue {
+    const VAL: u8 = 29;
+}
+
+#[derive(Debug)]
+pub struct JumpTrueW;
+impl OpCodeU32 for JumpTrueW {}
+impl OpCode for JumpTrueW {
+    const VAL: u8 = 30;
+}
+
+/** `Lt` followed by `JumpNotTrue` */
+#[derive(Debug)]
+pub struct LtJumpNotTrue;
+impl OpCodeU16 for LtJumpNotTrue {}
+impl OpCode for LtJumpNotTrue {
+    const VAL: u8 = 31;
+}
+
+#[derive(Debug)]
+pub struct LtJumpNotTrueW;
+impl OpCodeU32 for LtJumpNotTrueW {}
+impl OpCode for LtJumpNotTrueW {
+    const VAL: u8 = 32;
+}
+
+/** Joins the top n values into one string, converting each as `str` does */
+#[derive(Debug)]
+pub struct Concat;
+impl OpCodeU16 for Concat {}
+impl OpCode for Concat {
+    const VAL: u8 = 33;
+}
+
+/** Pushes a local and leaves null in its slot, for a local that won't be read again */
+#[derive(Debug)]
+pub struct TakeLVar;
+impl OpCodeU16 for TakeLVar {}
+impl OpCode for TakeLVar {
+    const VAL: u8 = 34;
+}
+
 #[derive(Debug)]
 pub struct Return;
 impl OpCodeNone for Return {}
@@ -233,6 +439,14 @@ impl OpCode for Return {
     const VAL: u8 = 50;
 }
 
+/** `Null` followed by `Return` */
+#[derive(Debug)]
+pub struct ReturnNull;
+impl OpCodeNone for ReturnNull {}
+impl OpCode for ReturnNull {
+    const VAL: u8 = 51;
+}
+
 #[derive(Debug)]
 pub struct Index;
 impl OpCodeNone for Index {}
@@ -247,6 +461,77 @@ impl OpCode for Call {
     const VAL: u8 = 103;
 }
 
+/** Calls a function in place of the current one, reusing its frame */
+#[derive(Debug)]
+pub struct TailCall;
+impl OpCodeU16 for TailCall {}
+impl OpCode for TailCall {
+    const VAL: u8 = 104;
+}
+
+/** Replaces an element of a collection, pushing the updated copy */
+#[derive(Debug)]
+pub struct SetIndex;
+impl OpCodeNone for SetIndex {}
+impl OpCode for SetIndex {
+    const VAL: u8 = 105;
+}
+
+/*
+ * Updates of a variable that refers to itself, like `s = s with x` or `t[i] = x`. The variable is
+ * changed in place when it holds the only reference to its collection, and the result is pushed.
+ */
+
+#[derive(Debug)]
+pub struct SetIndexGVar;
+impl OpCodeU16 for SetIndexGVar {}
+impl OpCode for SetIndexGVar {
+    const VAL: u8 = 106;
+}
+
+#[derive(Debug)]
+pub struct SetIndexLVar;
+impl OpCodeU16 for SetIndexLVar {}
+impl OpCode for SetIndexLVar {
+    const VAL: u8 = 107;
+}
+
+#[derive(Debug)]
+pub struct WithGVar;
+impl OpCodeU16 for WithGVar {}
+impl OpCode for WithGVar {
+    const VAL: u8 = 108;
+}
+
+#[derive(Debug)]
+pub struct WithLVar;
+impl OpCodeU16 for WithLVar {}
+impl OpCode for WithLVar {
+    const VAL: u8 = 109;
+}
+
+#[derive(Debug)]
+pub struct LessGVar;
+impl OpCodeU16 for LessGVar {}
+impl OpCode for LessGVar {
+    const VAL: u8 = 110;
+}
+
+#[derive(Debug)]
+pub struct LessLVar;
+impl OpCodeU16 for LessLVar {}
+impl OpCode for LessLVar {
+    const VAL: u8 = 111;
+}
+
+/** Replaces an element nested `n` collections deep, like `t[i][j] = x`, pushing the updated outer copy */
+#[derive(Debug)]
+pub struct SetIndexPath;
+impl OpCodeU16 for SetIndexPath {}
+impl OpCode for SetIndexPath {
+    const VAL: u8 = 112;
+}
+
 #[derive(Debug)]
 pub struct NullCoal;
 impl OpCodeNone for NullCoal {}
@@ -443,6 +728,14 @@ impl OpCode for Not {
     const VAL: u8 = 229;
 }
 
+/** `Const` followed by `Add` */
+#[derive(Debug)]
+pub struct AddConst;
+impl OpCodeU16 for AddConst {}
+impl OpCode for AddConst {
+    const VAL: u8 = 230;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -453,6 +746,11 @@ mod tests {
         assert_eq!(Const.make(258)[..], [Const::VAL, 1, 2]);
     }
 
+    #[test]
+    fn op_const32() {
+        assert_eq!(Const32.make(65_536)[..], [Const32::VAL, 0, 1, 0, 0]);
+    }
+
     #[test]
     fn op_add() {
         assert_eq!(Add.make()[..], [Add::VAL]);
@@ -16,6 +16,13 @@ pub fn lookup(byte: u8) -> Option<(&'static [usize], &'static str)> {
         ToTupleRn::VAL => Some((ToTupleRn::OPERAND_COUNTS, "ToTupleRn")),
         ToSetRn::VAL => Some((ToSetRn::OPERAND_COUNTS, "ToSetRn")),
         ToFn::VAL => Some((ToFn::OPERAND_COUNTS, "ToFn")),
+        Const32::VAL => Some((Const32::OPERAND_COUNTS, "Const32")),
+        GetLVar0::VAL => Some((GetLVar0::OPERAND_COUNTS, "GetLVar0")),
+        GetLVar1::VAL => Some((GetLVar1::OPERAND_COUNTS, "GetLVar1")),
+        GetLVar2::VAL => Some((GetLVar2::OPERAND_COUNTS, "GetLVar2")),
+        GetLVar3::VAL => Some((GetLVar3::OPERAND_COUNTS, "GetLVar3")),
+        IncLocal::VAL => Some((IncLocal::OPERAND_COUNTS, "IncLocal")),
+        Line::VAL => Some((Line::OPERAND_COUNTS, "Line")),
         
         Pop::VAL => Some((Pop::OPERAND_COUNTS, "Pop")),
         PushMatch::VAL => Some((PushMatch::OPERAND_COUNTS, "PushMatch")),
@@ -23,11 +30,30 @@ pub fn lookup(byte: u8) -> Option<(&'static [usize], &'static str)> {
         Jump::VAL => Some((Jump::OPERAND_COUNTS, "Jump")),
         JumpNotTrue::VAL => Some((JumpNotTrue::OPERAND_COUNTS, "JumpNotTrue")),
         JumpNotMatch::VAL => Some((JumpNotMatch::OPERAND_COUNTS, "JumpNotMatch")),
+        JumpW::VAL => Some((JumpW::OPERAND_COUNTS, "JumpW")),
+        JumpNotTrueW::VAL => Some((JumpNotTrueW::OPERAND_COUNTS, "JumpNotTrueW")),
+        JumpNotMatchW::VAL => Some((JumpNotMatchW::OPERAND_COUNTS, "JumpNotMatchW")),
+        JumpTrue::VAL => Some((JumpTrue::OPERAND_COUNTS, "JumpTrue")),
+        JumpTrueW::VAL => Some((JumpTrueW::OPERAND_COUNTS, "JumpTrueW")),
+        LtJumpNotTrue::VAL => Some((LtJumpNotTrue::OPERAND_COUNTS, "LtJumpNotTrue")),
+        LtJumpNotTrueW::VAL => Some((LtJumpNotTrueW::OPERAND_COUNTS, "LtJumpNotTrueW")),
+        Concat::VAL => Some((Concat::OPERAND_COUNTS, "Concat")),
+        TakeLVar::VAL => Some((TakeLVar::OPERAND_COUNTS, "TakeLVar")),
 
         Return::VAL => Some((Return::OPERAND_COUNTS, "Return")),
+        ReturnNull::VAL => Some((ReturnNull::OPERAND_COUNTS, "ReturnNull")),
 
         Index::VAL => Some((Index::OPERAND_COUNTS, "Index")),
         Call::VAL => Some((Call::OPERAND_COUNTS, "Call")),
+        TailCall::VAL => Some((TailCall::OPERAND_COUNTS, "TailCall")),
+        SetIndex::VAL => Some((SetIndex::OPERAND_COUNTS, "SetIndex")),
+        SetIndexGVar::VAL => Some((SetIndexGVar::OPERAND_COUNTS, "SetIndexGVar")),
+        SetIndexLVar::VAL => Some((SetIndexLVar::OPERAND_COUNTS, "SetIndexLVar")),
+        WithGVar::VAL => Some((WithGVar::OPERAND_COUNTS, "WithGVar")),
+        WithLVar::VAL => Some((WithLVar::OPERAND_COUNTS, "WithLVar")),
+        LessGVar::VAL => Some((LessGVar::OPERAND_COUNTS, "LessGVar")),
+        LessLVar::VAL => Some((LessLVar::OPERAND_COUNTS, "LessLVar")),
+        SetIndexPath::VAL => Some((SetIndexPath::OPERAND_COUNTS, "SetIndexPath")),
 
         NullCoal::VAL => Some((NullCoal::OPERAND_COUNTS, "NullCoal")),
         TupleStart::VAL => Some((TupleStart::OPERAND_COUNTS, "TupleStart")),
@@ -57,11 +83,47 @@ pub fn lookup(byte: u8) -> Option<(&'static [usize], &'static str)> {
         DynVar::VAL => Some((DynVar::OPERAND_COUNTS, "DynVar")),
         Size::VAL => Some((Size::OPERAND_COUNTS, "Size")),
         Not::VAL => Some((Not::OPERAND_COUNTS, "Not")),
-        other => {
-            println!("No idea how to print code: {}", other);
-            None
-        }
+        AddConst::VAL => Some((AddConst::OPERAND_COUNTS, "AddConst")),
+        _ => None,
+    }
+}
+
+/** A single decoded instruction, along with the position it was read from */
+#[derive(Debug, PartialEq)]
+pub struct Instruction {
+    pub pos: usize,
+    pub op: u8,
+    pub operands: Vec<usize>,
+}
+
+impl Instruction {
+    /** The number of bytes the instruction takes up, including the opcode */
+    pub fn width(&self) -> usize {
+        1 + lookup(self.op).unwrap().0.iter().sum::<usize>()
+    }
+}
+
+/** Decodes an entire instruction stream, panicking on any unknown opcode */
+pub fn decode(bytes: &[u8]) -> Vec<Instruction> {
+    let len = bytes.len();
+    let mut buf = bytes;
+
+    let mut instructions = vec![];
+    while buf.remaining() > 0 {
+        let pos = len - buf.remaining();
+        let op = buf.get_u8();
+        let (sizes, _) = lookup(op).unwrap_or_else(|| panic!("Unknown opcode {} at {}", op, pos));
+        let operands = sizes
+            .iter()
+            .map(|size| match *size {
+                2 => buf.get_u16() as usize,
+                4 => buf.get_u32() as usize,
+                _ => unreachable!(),
+            })
+            .collect();
+        instructions.push(Instruction { pos, op, operands });
     }
+    instructions
 }
 
 pub fn print_bytes(bytes: &Bytes) -> String {
@@ -91,6 +153,9 @@ fn print_op(buf: &mut dyn Buf, pos: usize) -> String {
             2 => {
                 output.push_str(&format!(" {}", buf.get_u16()))
             },
+            4 => {
+                output.push_str(&format!(" {}", buf.get_u32()))
+            },
             _ => unreachable!(),
         }
     }
@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use bytes::Bytes;
+
+use super::code::{self, is_jump, OpCode};
+use super::debug::{decode, lookup};
+use crate::compiler::compiler::Bytecode;
+use crate::object::object::BaseObject;
+
+/*
+ * The disassembly is laid out in sections, which is also the format that the assembler reads:
+ *
+ * .const 0 int 100
+ * .const 1 func req=1 opt=0 locals=0
+ * .global 0 c
+ *
+ * .code main
+ *    0: Const 0 ; int 100
+ * ...
+ * .code 1
+ * L0:
+ *    0: GetLVar 0
+ * ...
+ *
+ * Every function constant gets its own `.code` section, keyed by its index in the constant pool.
+ */
+
+/** Disassembles the main program and every function constant in the bytecode */
+pub fn disassemble(bytecode: &Bytecode) -> String {
+    let mut output = String::new();
+
+    for (index, constant) in bytecode.constants.iter().enumerate() {
+        writeln!(output, ".const {} {}", index, describe_const(constant)).unwrap();
+    }
+    for (index, name) in bytecode.global_names.iter().enumerate() {
+        writeln!(output, ".global {} {}", index, name).unwrap();
+    }
+
+    output.push_str("\n.code main\n");
+    disassemble_code(&bytecode.instuctions, bytecode, &mut output);
+
+    for (index, constant) in bytecode.constants.iter().enumerate() {
+        if let BaseObject::Function { ins, .. } = constant {
+            writeln!(output, ".code {}", index).unwrap();
+            disassemble_code(ins.bytes(), bytecode, &mut output);
+        }
+    }
+
+    output
+}
+
+/** The textual form of a constant, as it appears in both `.const` lines and `Const` comments */
+pub fn describe_const(constant: &BaseObject) -> String {
+    match constant {
+        BaseObject::Null => "null".to_owned(),
+        BaseObject::True => "true".to_owned(),
+        BaseObject::False => "false".to_owned(),
+        BaseObject::Integer(val) => format!("int {}", val),
+        BaseObject::Float(val) => format!("float {:?}", val),
+        BaseObject::String(val) => format!("str {:?}", val),
+        BaseObject::Function {
+            locals,
+            req_params,
+            opt_params,
+            ..
+        } => format!("func req={} opt={} locals={}", req_params, opt_params, locals),
+        other => format!("{:?}", other),
+    }
+}
+
+fn disassemble_code(bytes: &Bytes, bytecode: &Bytecode, output: &mut String) {
+    let instructions = decode(bytes);
+
+    // Labels are numbered in the order that their targets appear in the function
+    let labels: BTreeMap<usize, usize> = instructions
+        .iter()
+        .filter(|ins| is_jump(ins.op))
+        .map(|ins| ins.operands[0])
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .enumerate()
+        .map(|(label, target)| (target, label))
+        .collect();
+
+    for ins in instructions.iter() {
+        if let Some(label) = labels.get(&ins.pos) {
+            writeln!(output, "L{}:", label).unwrap();
+        }
+
+        let name = lookup(ins.op).unwrap().1;
+        write!(output, "{:>4}: {}", ins.pos, name).unwrap();
+
+        if is_jump(ins.op) {
+            write!(output, " L{}", labels[&ins.operands[0]]).unwrap();
+        } else {
+            for operand in ins.operands.iter() {
+                write!(output, " {}", operand).unwrap();
+            }
+        }
+
+        let comment = match ins.op {
+            code::Const::VAL | code::Const32::VAL | code::AddConst::VAL => bytecode.constants.get(ins.operands[0]).map(describe_const),
+            code::GetGVar::VAL
+            | code::SetGVar::VAL
+            | code::SetIndexGVar::VAL
+            | code::WithGVar::VAL
+            | code::LessGVar::VAL => {
+                bytecode.global_names.get(ins.operands[0]).cloned()
+            }
+            _ => None,
+        };
+        if let Some(comment) = comment {
+            write!(output, " ; {}", comment).unwrap();
+        }
+        output.push('\n');
+    }
+
+    // A jump can target the very end of a function when the jump skips over the final instruction
+    if let Some(label) = labels.get(&bytes.len()) {
+        writeln!(output, "L{}:", label).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+    use crate::compiler::compiler::Compiler;
+    use crate::parser::parser;
+
+    fn disassemble_program(input: &str) -> String {
+        let wrapped_input = format!("program :any; {}", input);
+        let mut c = Compiler::new();
+        c.compile_program(parser::parse_from_program(&wrapped_input).unwrap());
+        disassemble(&c.finish())
+    }
+
+    #[test]
+    fn constants_and_globals() {
+        assert_eq!(
+            disassemble_program(r#"a = 1; b = "two"; a;"#),
+            [
+                ".const 0 int 1",
+                ".const 1 str \"two\"",
+                ".global 0 a",
+                ".global 1 b",
+                "",
+                ".code main",
+                "   0: Const 0 ; int 1",
+                "   3: SetGVar 0 ; a",
+                "   6: Pop",
+                "   7: Const 1 ; str \"two\"",
+                "  10: SetGVar 1 ; b",
+                "  13: Pop",
+                "  14: GetGVar 0 ; a",
+                "  17: Pop",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn jump_labels() {
+        assert_eq!(
+            disassemble_program("if true ? 1 : 2;"),
+            [
+                ".const 0 int 1",
+                ".const 1 int 2",
+                "",
+                ".code main",
+                "   0: True",
+                "   1: JumpNotTrue L0",
+                "   4: Const 0 ; int 1",
+                "   7: Jump L1",
+                "L0:",
+                "  10: Const 1 ; int 2",
+                "L1:",
+                "  13: Pop",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn nested_functions() {
+        assert_eq!(
+            disassemble_program("f = func(a, b?) { g = () => 2; g() + a };"),
+            [
+                ".const 0 int 2",
+                ".const 1 func req=0 opt=0 locals=0",
+                ".const 2 func req=1 opt=1 locals=1",
+                ".global 0 f",
+                "",
+                ".code main",
+                "   0: Const 2 ; func req=1 opt=1 locals=1",
+                "   3: ToFn 0",
+                "   6: SetGVar 0 ; f",
+                "   9: Pop",
+                ".code 1",
+                "   0: Const 0 ; int 2",
+                "   3: Return",
+                ".code 2",
+                "   0: Const 1 ; func req=0 opt=0 locals=0",
+                "   3: ToFn 0",
+                "   6: SetLVar 2",
+                "   9: Pop",
+                "  10: GetLVar2",
+                "  11: Call 0",
+                "  14: GetLVar0",
+                "  15: Add",
+                "  16: Return",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -1,2 +1,6 @@
+pub mod assembler;
+#[allow(clippy::module_inception)]
 pub mod code;
 pub mod debug;
+pub mod disassembler;
+pub mod words;
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use bytes::Bytes;
+
+use super::code::{self, is_jump, narrow_jump, OpCode};
+use super::debug::decode;
+
+/*
+ * Bytes are how instructions are written out and read back in, but decoding them again on every
+ * step is wasted work for the VM. When a function's code is loaded, it's also decoded into a list
+ * of fixed-size words that the VM steps through by index:
+ * - Each word holds the opcode and its operand (zero if it doesn't take one)
+ * - Jump targets are indices into the list rather than byte offsets
+ * - Wide jumps and `Const32` become their narrow forms, since the operand always fits
+ * - `Line` markers are dropped, and collected into a table of where each source line starts
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Word {
+    pub op: u8,
+    pub arg: u32,
+}
+
+/** Pairs of (word index, source line), ordered by index */
+type LineTable = Box<[(usize, usize)]>;
+
+/** Names the compiler knew a function by, which only debugging tools make use of */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    /** The name the function was assigned to when it was defined */
+    pub name: Option<String>,
+    /** The source line of the statement that defined the function */
+    pub line: Option<usize>,
+    /** Names of the params and locals, in the order of their stack slots */
+    pub locals: Vec<String>,
+}
+
+/** A function's instructions, in both their byte and decoded forms */
+#[derive(Debug)]
+pub struct Code {
+    bytes: Bytes,
+    words: Box<[Word]>,
+    /** Byte offset of each word */
+    offsets: Box<[usize]>,
+    lines: LineTable,
+    debug_info: DebugInfo,
+}
+
+impl Code {
+    pub fn new(bytes: Bytes) -> Self {
+        Self::with_debug_info(bytes, DebugInfo::default())
+    }
+
+    pub fn with_debug_info(bytes: Bytes, debug_info: DebugInfo) -> Self {
+        let (words, offsets, lines) = decode_words(&bytes);
+        Self { bytes, words, offsets, lines, debug_info }
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    pub fn words(&self) -> &[Word] {
+        &self.words
+    }
+
+    /** Where the word at `index` is in the bytes, where one past the last word is the end */
+    pub fn byte_offset(&self, index: usize) -> usize {
+        self.offsets.get(index).copied().unwrap_or(self.bytes.len())
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    pub fn has_lines(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    /** The source line of the statement that the word at `index` belongs to */
+    pub fn line_at(&self, index: usize) -> Option<usize> {
+        let entry = self.lines.partition_point(|&(start, _)| start <= index);
+        entry.checked_sub(1).map(|entry| self.lines[entry].1)
+    }
+
+    /** The source line whose statement starts at `index`, if one does */
+    pub fn line_starting_at(&self, index: usize) -> Option<usize> {
+        let entry = self.lines.partition_point(|&(start, _)| start < index);
+        self.lines.get(entry).filter(|&&(start, _)| start == index).map(|&(_, line)| line)
+    }
+}
+
+// The words are derived from the bytes, so the bytes are all that needs comparing
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Hash for Code {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+fn decode_words(bytes: &[u8]) -> (Box<[Word]>, Box<[usize]>, LineTable) {
+    let instructions = decode(bytes);
+    // A jump to a `Line` marker lands on the word that follows it
+    let mut indices: HashMap<usize, usize> = HashMap::new();
+    let mut lines: Vec<(usize, usize)> = vec![];
+    let mut word_count = 0;
+    for ins in instructions.iter() {
+        indices.insert(ins.pos, word_count);
+        if ins.op == code::Line::VAL {
+            // Statements that compile to nothing leave markers with no words between them
+            if lines.last().is_some_and(|&(start, _)| start == word_count) {
+                lines.pop();
+            }
+            lines.push((word_count, ins.operands[0]));
+        } else {
+            word_count += 1;
+        }
+    }
+    indices.insert(bytes.len(), word_count);
+
+    let instructions: Vec<_> = instructions.into_iter().filter(|ins| ins.op != code::Line::VAL).collect();
+    let offsets = instructions.iter().map(|ins| ins.pos).collect();
+    let words = instructions
+        .into_iter()
+        .map(|ins| {
+            let arg = ins.operands.first().copied().unwrap_or(0);
+            if is_jump(ins.op) {
+                let target = *indices
+                    .get(&arg)
+                    .unwrap_or_else(|| panic!("Jump at {} lands mid-instruction", ins.pos));
+                Word { op: narrow_jump(ins.op), arg: target as u32 }
+            } else if ins.op == code::Const32::VAL {
+                Word { op: code::Const::VAL, arg: arg as u32 }
+            } else {
+                Word { op: ins.op, arg: arg as u32 }
+            }
+        })
+        .collect();
+    (words, offsets, lines.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Code, Word};
+    use crate::code::code::{self, OpCode};
+    use bytes::Bytes;
+
+    #[test] #[rustfmt::skip]
+    fn decodes_words() {
+        let code = Code::new(Bytes::from(vec![
+            code::Const32::VAL, 0, 1, 0, 0,
+            code::JumpNotTrueW::VAL, 0, 0, 0, 13,
+            code::Jump::VAL, 0, 14,
+            code::Null::VAL,
+        ]));
+        assert_eq!(
+            code.words(),
+            [
+                Word { op: code::Const::VAL, arg: 65_536 },
+                Word { op: code::JumpNotTrue::VAL, arg: 3 },
+                Word { op: code::Jump::VAL, arg: 4 },
+                Word { op: code::Null::VAL, arg: 0 },
+            ]
+        );
+    }
+
+    #[test] #[rustfmt::skip]
+    fn collects_lines() {
+        let code = Code::new(Bytes::from(vec![
+            code::Line::VAL, 0, 0, 0, 1,
+            code::True::VAL,
+            code::JumpNotTrue::VAL, 0, 14,
+            code::Line::VAL, 0, 0, 0, 2,
+            code::Line::VAL, 0, 0, 0, 3,
+            code::Line::VAL, 0, 0, 0, 4,
+            code::Null::VAL,
+        ]));
+        assert_eq!(
+            code.words(),
+            [
+                Word { op: code::True::VAL, arg: 0 },
+                Word { op: code::JumpNotTrue::VAL, arg: 2 },
+                Word { op: code::Null::VAL, arg: 0 },
+            ]
+        );
+        // Lines 2 and 3 have no instructions of their own, so the `Null` belongs to line 4
+        assert_eq!(code.line_at(1), Some(1));
+        assert_eq!(code.line_at(2), Some(4));
+        assert_eq!(code.line_starting_at(0), Some(1));
+        assert_eq!(code.line_starting_at(1), None);
+        assert_eq!(code.line_starting_at(2), Some(4));
+        assert_eq!([0, 1, 2, 3].map(|index| code.byte_offset(index)), [5, 6, 24, 25]);
+    }
+}
@@ -1,17 +1,34 @@
+use std::collections::HashMap;
 use std::rc::Rc;
 
 use bytes::{BufMut, Bytes, BytesMut};
-use crate::code::code::{self, OpCodeMake, OpCodeMakeWithU16};
-use crate::code::debug::print_bytes;
+use crate::code::code::{self, OpCodeMake, OpCodeMakeWithU16, OpCodeMakeWithU32};
+use crate::code::words::{Code, DebugInfo};
 use crate::object::object::BaseObject;
-use crate::parser::ast::{BinOp, Case, ExprST, Former, Postfix, PreOp, Program, LHS};
+use crate::vm::native::NativeFunction;
+use crate::parser::ast::{BinOp, Case, ExprST, Former, Postfix, PreOp, Program, Stmt, LHS};
+use super::layout::{pack, unpack};
+use super::optimizer::optimize_program;
+use super::peephole::peephole;
+use super::specialize::specialize;
 use super::symbols::{Scope, SymbolRegistry};
 
 pub struct Compiler {
     constants: Vec<BaseObject>,
+    const_map: HashMap<ConstKey, usize>,
     symbol_map: SymbolRegistry,
+    optimize: bool,
+    line_info: bool,
+    /** Set when the function about to be compiled is being assigned to a name */
+    function_name: Option<String>,
+    /** Line of the statement being compiled */
+    line: usize,
 
     scopes: Vec<ScopeCtx>,
+    /** Number of constants handed out by earlier chunks */
+    chunk_constants: usize,
+    /** Natives registered since the last chunk, with the globals they're bound to */
+    natives: Vec<(usize, NativeFunction)>,
 }
 
 pub struct BytecodeRef<'a> {
@@ -20,30 +37,65 @@ pub struct BytecodeRef<'a> {
     pub global_count: usize,
 }
 
+#[derive(Debug)]
 pub struct Bytecode {
     pub instuctions: Bytes,
     pub constants: Vec<BaseObject>,
     pub global_count: usize,
+    pub global_names: Vec<String>,
+    /** Native functions, to be stored in the globals at these indices before the program runs */
+    pub natives: Vec<(usize, NativeFunction)>,
+}
+
+/** Hashable stand-in for the constants that can be interned (floats are keyed by their bits) */
+#[derive(PartialEq, Eq, Hash)]
+enum ConstKey {
+    Integer(i64),
+    Float(u64),
+    String(String),
 }
 
 struct ScopeCtx {
     pub instructions: BytesMut,
 }
 
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Compiler {
     pub fn new() -> Self {
+        Self::with_optimizations(false)
+    }
+
+    /** When enabled, the AST is optimized before compiling, and the peephole pass runs after */
+    pub fn with_optimizations(optimize: bool) -> Self {
         let global_scope = ScopeCtx {
             instructions: BytesMut::new(),
         };
 
         Compiler {
             constants: vec![],
+            const_map: HashMap::new(),
             symbol_map: SymbolRegistry::new(),
+            optimize,
+            line_info: false,
+            function_name: None,
+            line: 0,
 
             scopes: vec![global_scope],
+            chunk_constants: 0,
+            natives: vec![],
         }
     }
 
+    /** When enabled, a `Line` marker is emitted at the start of each statement for debuggers */
+    pub fn set_line_info(&mut self, enabled: bool) {
+        self.line_info = enabled;
+    }
+
     fn enter_scope(&mut self) {
         let new_scope = ScopeCtx {
             instructions: BytesMut::new(),
@@ -52,11 +104,19 @@ impl Compiler {
         self.symbol_map.enter_scope();
     }
 
-    fn leave_scope(&mut self) -> (Bytes, usize) {
+    /** Returns the scope's finished instructions, along with the names of its locals */
+    fn leave_scope(&mut self) -> (Bytes, Vec<String>) {
         let top_scope = self.scopes.pop().unwrap();
-        let local_count = self.symbol_map.size();
+        let local_names = self.symbol_map.names();
         self.symbol_map.exit_scope();
-        (top_scope.instructions.freeze(), local_count)
+        (self.finish_instructions(&top_scope.instructions).freeze(), local_names)
+    }
+
+    /** Final passes over a scope's instructions once all of its jumps have been patched */
+    fn finish_instructions(&self, instructions: &BytesMut) -> BytesMut {
+        let ops = unpack(instructions);
+        let ops = if self.optimize { peephole(ops) } else { ops };
+        pack(&specialize(ops, &self.constants))
     }
 
     fn cur_scope_mut(&mut self) -> &mut ScopeCtx {
@@ -75,21 +135,56 @@ impl Compiler {
     }
 
     pub fn compile_program(&mut self, node: Program) {
-        self.compile_expr_list(node.expressions, true);
+        let node = if self.optimize { optimize_program(node) } else { node };
+        for stmt in node.expressions.into_iter() {
+            self.compile_stmt(stmt, false);
+            self.emit(&code::Pop.make());
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: Stmt, tail: bool) {
+        self.line = stmt.line;
+        if self.line_info {
+            self.emit(&code::Line.make(stmt.line as u32));
+        }
+        self.compile_node(stmt.expr, tail);
     }
 
     pub fn compile_expr_list(&mut self, exprs: Vec<ExprST>, with_pop: bool) {
         for expr in exprs.into_iter() {
             self.compile_expr(expr);
-            // OPTIMIZE: If the last op after the above line runs is something that would only
-            // push a value onto the stack, just remove it and omit the following pop.
+            // Values that are pushed only to be popped here are cleaned up by the peephole pass
             if with_pop {
                 self.emit(&code::Pop.make());
             }
         }
     }
 
+    /**
+     * Compiles a block of expressions which evaluates to its last expression, or to null when
+     * the block is empty or ends with a semicolon.
+     */
+    fn compile_block(&mut self, mut stmts: Vec<Stmt>, null_return: bool, tail: bool) {
+        let last = if null_return { None } else { stmts.pop() };
+        for stmt in stmts.into_iter() {
+            self.compile_stmt(stmt, false);
+            self.emit(&code::Pop.make());
+        }
+        match last {
+            Some(last) => self.compile_stmt(last, tail),
+            None => self.emit(&code::Null.make()),
+        }
+    }
+
     pub fn compile_expr(&mut self, node: ExprST) {
+        self.compile_node(node, false);
+    }
+
+    /**
+     * Compiles an expression, where `tail` marks that its value is immediately returned from the
+     * current function. Calls in tail position reuse the caller's frame instead of adding one.
+     */
+    fn compile_node(&mut self, node: ExprST, tail: bool) {
         match node {
             ExprST::Null => {
                 self.emit(&code::Null.make());
@@ -112,9 +207,16 @@ impl Compiler {
                 self.compile_ident(name);
             }
             ExprST::String(value) => {
-                let const_ptr = self.add_const(BaseObject::String(value.to_owned()));
+                let const_ptr = self.add_const(BaseObject::String(value.into_owned().into()));
                 self.emit_const(const_ptr);
             }
+            ExprST::Interpolation(parts) => {
+                let count = parts.len() as u16;
+                for part in parts {
+                    self.compile_expr(part);
+                }
+                self.emit(&code::Concat.make(count));
+            }
             ExprST::TupleLiteral(former) => {
                 self.compile_former(former, code::ToTuple, code::ToTupleRn);
             }
@@ -159,8 +261,13 @@ impl Compiler {
                     }
                     Postfix::Call(args) => {
                         let arg_count = args.len() as u16;
-                        self.compile_expr_list(args, false);
-                        self.emit(&code::Call.make(arg_count));
+                        if tail {
+                            self.compile_tail_args(args);
+                            self.emit(&code::TailCall.make(arg_count));
+                        } else {
+                            self.compile_expr_list(args, false);
+                            self.emit(&code::Call.make(arg_count));
+                        }
                     }
                     _ => unimplemented!(),
                 }
@@ -172,35 +279,20 @@ impl Compiler {
             } => {
                 self.compile_expr(*condition);
 
-                let jnt_operand_ptr = self.ins_len() + 1;
-                self.emit(&code::JumpNotTrue.make(u16::MAX));
-                self.compile_expr(*consequence);
-                let jump_operand_ptr = self.ins_len() + 1;
-                self.emit(&code::Jump.make(u16::MAX));
-                let jnt_location = self.cur_ip();
-                self.compile_expr(*alternative);
-
-                self.overwrite_u16(jnt_operand_ptr, jnt_location);
-                self.overwrite_u16(jump_operand_ptr, self.cur_ip());
+                let jnt_operand_ptr = self.emit_jump(&code::JumpNotTrueW.make(u32::MAX));
+                self.compile_node(*consequence, tail);
+                let jump_operand_ptr = self.emit_jump(&code::JumpW.make(u32::MAX));
+                self.patch_jump(jnt_operand_ptr);
+                self.compile_node(*alternative, tail);
+                self.patch_jump(jump_operand_ptr);
             }
             ExprST::Switch { input, cases } => match input {
                 Some(expr) => self.compile_match_switch(*expr, cases),
-                None => self.compile_bool_switch(cases),
+                None => self.compile_bool_switch(cases, tail),
             },
             ExprST::Assign { left, right } => {
-                self.compile_expr(*right);
                 match left {
-                    LHS::Ident {
-                        target,
-                        selectors: _,
-                    } => {
-                        let sym = self.symbol_map.register(target);
-                        let index = sym.index;
-                        match sym.scope {
-                            Scope::GLOBAL => self.emit(&code::SetGVar.make(index)),
-                            Scope::LOCAL => self.emit(&code::SetLVar.make(index)),
-                        }
-                    }
+                    LHS::Ident { target, selectors } => self.compile_assign(target, selectors, *right),
                     _ => unimplemented!(),
                 };
             }
@@ -212,22 +304,20 @@ impl Compiler {
                 body,
                 null_return,
             } => {
+                let name = self.function_name.take();
+                let line = self.line;
                 self.enter_scope();
 
                 for p in req_params.iter() { self.symbol_map.register(p); }
                 for p in opt_params.iter() { self.symbol_map.register(p); }
                 for p in locked_params.iter() { self.symbol_map.register(p); }
 
-                self.compile_expr_list(body, true);
-                if self.ins_len() > 0 {
-                    self.handle_null_return(null_return);
-                } else {
-                    self.emit(&code::Null.make())
-                }
+                self.compile_block(body, null_return, true);
                 self.emit(&code::Return.make());
-                let (func_code, local_count) = self.leave_scope();
-
-                println!("Bytes for my function are:\n{}\n:", print_bytes(&func_code));
+                let (func_code, local_names) = self.leave_scope();
+                // The body's statements moved the line along, but the rest belongs to the definition
+                self.line = line;
+                let local_count = local_names.len();
 
                 let req_count = req_params.len() as u16;
                 let opt_count = opt_params.len() as u16;
@@ -235,7 +325,7 @@ impl Compiler {
                 
                 // First, build the const object without the locked values
                 let const_ptr = self.add_const(BaseObject::Function {
-                    ins: Rc::new(func_code),
+                    ins: Rc::new(Code::with_debug_info(func_code, DebugInfo { name, line: Some(line), locals: local_names })),
                     locals: local_count - (req_count + opt_count + locked_count) as usize,
                     req_params: req_count,
                     opt_params: opt_count,
@@ -250,7 +340,9 @@ impl Compiler {
             }
 
             ExprST::Return(expr) => {
-                self.compile_expr(*expr);
+                // The main program has no caller whose frame could be reused
+                let in_function = self.scopes.len() > 1;
+                self.compile_node(*expr, in_function);
                 self.emit(&code::Return.make());
             }
 
@@ -258,7 +350,7 @@ impl Compiler {
         };
     }
 
-    pub fn check(&self) -> BytecodeRef {
+    pub fn check(&self) -> BytecodeRef<'_> {
         BytecodeRef {
             instructions: self.current_instructions(),
             constants: &self.constants,
@@ -266,18 +358,55 @@ impl Compiler {
         }
     }
 
-    pub fn finish(self) -> Bytecode {
-        let mut scopes = self.scopes;
-        let instructions = scopes.pop().unwrap().instructions;
+    pub fn finish(mut self) -> Bytecode {
+        let instructions = self.finish_instructions(self.current_instructions());
         Bytecode {
             instuctions: instructions.freeze(),
             constants: self.constants,
             global_count: self.symbol_map.size(),
+            global_names: self.symbol_map.names(),
+            natives: std::mem::take(&mut self.natives),
+        }
+    }
+
+    /**
+     * Takes the bytecode compiled since the last chunk, leaving the compiler ready to compile more
+     * code that can see the same globals. Only the constants added since the last chunk are
+     * included, for a VM that has already loaded the earlier ones.
+     */
+    pub fn finish_chunk(&mut self) -> Bytecode {
+        let instructions = self.finish_instructions(self.current_instructions());
+        self.cur_scope_mut().instructions.clear();
+        let constants = self.constants[self.chunk_constants..].to_vec();
+        self.chunk_constants = self.constants.len();
+        Bytecode {
+            instuctions: instructions.freeze(),
+            constants,
+            global_count: self.symbol_map.size(),
+            global_names: self.symbol_map.names(),
+            natives: std::mem::take(&mut self.natives),
         }
     }
 
-    fn cur_ip(&self) -> u16 {
-        self.ins_len() as u16
+    /** Binds a native function to a global of the same name, for the code compiled after this */
+    pub fn register_native(&mut self, native: NativeFunction) {
+        let index = self.global_index(&native.name);
+        self.natives.push((index, native));
+    }
+
+    /** Index of a global variable, registering it if no code has used it yet */
+    pub fn global_index(&mut self, name: &str) -> usize {
+        self.symbol_map.register(name).index as usize
+    }
+
+    /** Index of a global variable that's already been registered */
+    pub fn find_global(&self, name: &str) -> Option<usize> {
+        self.symbol_map.lookup(name).map(|sym| sym.index as usize)
+    }
+
+    fn cur_ip(&self) -> u32 {
+        u32::try_from(self.ins_len())
+            .unwrap_or_else(|_| panic!("Function is too large to compile ({} bytes of bytecode)", self.ins_len()))
     }
 
     fn emit(&mut self, bytes: &Bytes) {
@@ -285,7 +414,13 @@ impl Compiler {
     }
 
     fn emit_const(&mut self, const_ptr: usize) {
-        self.emit(&code::Const.make(const_ptr as u16))
+        if let Ok(ptr) = u16::try_from(const_ptr) {
+            self.emit(&code::Const.make(ptr))
+        } else if let Ok(ptr) = u32::try_from(const_ptr) {
+            self.emit(&code::Const32.make(ptr))
+        } else {
+            panic!("Program has too many constants ({})", const_ptr + 1);
+        }
     }
 
     fn emit_binop(&mut self, binop: BinOp) {
@@ -330,14 +465,25 @@ impl Compiler {
         }
     }
 
-    // OPTIMIZE: Constants could be added to a hashmap keyed by constant's value, and
-    // the value of the hashmap would be an incrementing index which is returned by the
-    // `add_const` fn. Before passing constant list to VM, constant map would be converted
-    // to a vector. However, I think this may result in few space saves since programs
-    // with many many similar constants aren't common.
+    // Identical literals share a single slot in the constant pool. Functions are never interned
+    // since each literal is its own value (and comparing bytecode isn't worth it).
     fn add_const(&mut self, constant: BaseObject) -> usize {
+        let key = match &constant {
+            BaseObject::Integer(val) => Some(ConstKey::Integer(*val)),
+            BaseObject::Float(val) => Some(ConstKey::Float(val.to_bits())),
+            BaseObject::String(val) => Some(ConstKey::String(val.to_string())),
+            _ => None,
+        };
+        if let Some(&ptr) = key.as_ref().and_then(|key| self.const_map.get(key)) {
+            return ptr;
+        }
+
         self.constants.push(constant);
-        self.constants.len() - 1
+        let ptr = self.constants.len() - 1;
+        if let Some(key) = key {
+            self.const_map.insert(key, ptr);
+        }
+        ptr
     }
 
     fn overwrite(&mut self, at: usize, value: Bytes) {
@@ -347,35 +493,169 @@ impl Compiler {
         }
     }
 
-    fn overwrite_u16(&mut self, at: usize, value: u16) {
-        let mut bytes = BytesMut::with_capacity(2);
-        bytes.put_u16(value);
+    fn overwrite_u32(&mut self, at: usize, value: u32) {
+        let mut bytes = BytesMut::with_capacity(4);
+        bytes.put_u32(value);
         self.overwrite(at, bytes.freeze())
     }
 
+    /**
+     * Emits a (wide) jump with a placeholder target, returning the position of its operand so it
+     * can be patched later. Jumps are narrowed where possible when the scope is finished.
+     */
+    fn emit_jump(&mut self, jump_bytes: &Bytes) -> usize {
+        let operand_ptr = self.ins_len() + 1;
+        self.emit(jump_bytes);
+        operand_ptr
+    }
+
+    /** Points a previously emitted jump at the current end of the instructions */
+    fn patch_jump(&mut self, operand_ptr: usize) {
+        self.overwrite_u32(operand_ptr, self.cur_ip());
+    }
+
+    fn compile_assign(&mut self, target: &str, mut selectors: Vec<Postfix>, right: ExprST) {
+        // A variable that already exists in this scope can have its collection updated in place
+        let in_scope = self.symbol_map.lookup_current(target).is_some();
+        match (selectors.pop(), right) {
+            (
+                None,
+                ExprST::Infix {
+                    op: op @ (BinOp::With | BinOp::Less),
+                    left,
+                    right,
+                },
+            ) if in_scope && matches!(*left, ExprST::Ident(name) if name == target) => {
+                self.compile_ident(target);
+                self.compile_expr(*right);
+                match op {
+                    BinOp::With => self.emit_var_update(target, code::WithGVar, code::WithLVar),
+                    _ => self.emit_var_update(target, code::LessGVar, code::LessLVar),
+                }
+                return;
+            }
+            (None, right) => {
+                // Functions are labelled with the name they're assigned to, for debugging tools
+                if matches!(right, ExprST::Function { .. }) {
+                    self.function_name = Some(target.to_owned());
+                }
+                self.compile_expr(right)
+            }
+            // `t[i] = v` stores an updated copy of `t`, and evaluates to that copy
+            (Some(Postfix::Index(index)), right) if selectors.is_empty() => {
+                self.compile_ident(target);
+                self.compile_expr(*index);
+                self.compile_expr(right);
+                if in_scope {
+                    self.emit_var_update(target, code::SetIndexGVar, code::SetIndexLVar);
+                    return;
+                }
+                self.emit(&code::SetIndex.make());
+            }
+            // `t[i][j] = v` stores a copy of `t` with the element of `t[i]` replaced
+            (Some(Postfix::Index(last)), right) if selectors.iter().all(|s| matches!(s, Postfix::Index(_))) => {
+                let depth = selectors.len() as u16 + 1;
+                self.compile_ident(target);
+                for selector in selectors {
+                    let Postfix::Index(index) = selector else { unreachable!() };
+                    self.compile_expr(*index);
+                }
+                self.compile_expr(*last);
+                self.compile_expr(right);
+                self.emit(&code::SetIndexPath.make(depth));
+            }
+            (Some(last), _) => {
+                let unsupported = selectors.iter().chain([&last]).find(|s| !matches!(s, Postfix::Index(_))).unwrap();
+                let form = match unsupported {
+                    Postfix::Call(_) => "a call",
+                    Postfix::Range(..) => "a range",
+                    Postfix::Pick(_) => "a pick",
+                    Postfix::Index(_) => unreachable!(),
+                };
+                panic!("Can't assign to {} of '{}', only to its indexes", form, target);
+            }
+        }
+
+        let sym = self.symbol_map.register(target);
+        let index = sym.index;
+        match sym.scope {
+            Scope::GLOBAL => self.emit(&code::SetGVar.make(index)),
+            Scope::LOCAL => self.emit(&code::SetLVar.make(index)),
+        }
+    }
+
+    /** Emits one of the opcodes that update a variable in place, picked by the variable's scope */
+    fn emit_var_update(
+        &mut self,
+        target: &str,
+        global_op: impl OpCodeMakeWithU16,
+        local_op: impl OpCodeMakeWithU16,
+    ) {
+        let sym = self.symbol_map.lookup_current(target).unwrap();
+        let bytes = match sym.scope {
+            Scope::GLOBAL => global_op.make(sym.index),
+            Scope::LOCAL => local_op.make(sym.index),
+        };
+        self.emit(&bytes);
+    }
+
     fn compile_ident(&mut self, name: &str) {
         let sym = self
             .symbol_map
             .lookup(name)
-            .expect(&format!("'{}' is undefined in current scope", name));
+            .unwrap_or_else(|| panic!("'{}' is undefined in current scope", name));
         match sym.scope {
             Scope::GLOBAL => self.emit(&code::GetGVar.make(sym.index)),
             Scope::LOCAL => self.emit(&code::GetLVar.make(sym.index)),
         }
     }
 
+    /**
+     * Compiles the args of a call in tail position. The caller's frame is gone once the call is
+     * made, so an arg like `s with x` takes the local `s` out of its slot when nothing after it in
+     * the call reads `s`. That leaves the collection with a single reference, so it's updated in
+     * place rather than copied.
+     */
+    fn compile_tail_args(&mut self, args: Vec<ExprST>) {
+        let takes: Vec<Option<u16>> = (0..args.len()).map(|i| self.takeable_local(&args[i], &args[i + 1..])).collect();
+        for (arg, take) in args.into_iter().zip(takes) {
+            match (arg, take) {
+                (ExprST::Infix { op, right, .. }, Some(index)) => {
+                    self.emit(&code::TakeLVar.make(index));
+                    self.compile_expr(*right);
+                    self.emit_binop(op);
+                }
+                (arg, _) => self.compile_expr(arg),
+            }
+        }
+    }
+
+    /** The local that a tail call's `local with x` or `local less x` arg can take, if any */
+    fn takeable_local(&self, arg: &ExprST, later_args: &[ExprST]) -> Option<u16> {
+        let ExprST::Infix { op: BinOp::With | BinOp::Less, left, right } = arg else {
+            return None;
+        };
+        let &ExprST::Ident(name) = left.as_ref() else {
+            return None;
+        };
+        let sym = self.symbol_map.lookup(name)?;
+        let read_later = mentions(right, name) || later_args.iter().any(|arg| mentions(arg, name));
+        (sym.scope == Scope::LOCAL && !read_later).then_some(sym.index)
+    }
+
     fn compile_match_switch(&mut self, input: ExprST, cases: Vec<Case>) {
         self.compile_expr(input);
         self.emit(&code::PushMatch.make());
-        self.compile_switch_cases(cases, code::JumpNotMatch.make(u16::MAX));
+        // The match value has to be popped after the case, so nothing here is in tail position
+        self.compile_switch_cases(cases, code::JumpNotMatchW.make(u32::MAX), false);
         self.emit(&code::PopMatch.make());
     }
 
-    fn compile_bool_switch(&mut self, cases: Vec<Case>) {
-        self.compile_switch_cases(cases, code::JumpNotTrue.make(u16::MAX));
+    fn compile_bool_switch(&mut self, cases: Vec<Case>, tail: bool) {
+        self.compile_switch_cases(cases, code::JumpNotTrueW.make(u32::MAX), tail);
     }
 
-    fn compile_switch_cases(&mut self, cases: Vec<Case>, cond_jump_bytes: Bytes) {
+    fn compile_switch_cases(&mut self, cases: Vec<Case>, cond_jump_bytes: Bytes, tail: bool) {
         let mut jmp_operand_ptrs: Vec<usize> = vec![];
         let mut last_cond_jump_operand_ptr: Option<usize> = None;
         let mut has_default = false;
@@ -387,23 +667,19 @@ impl Compiler {
         } in cases.into_iter()
         {
             if let Some(ptr) = last_cond_jump_operand_ptr {
-                self.overwrite_u16(ptr, self.cur_ip());
+                self.patch_jump(ptr);
             }
 
             // Tilde case causes condition to be None, so we don't add a JUMP_NOT_TRUE
             let default_case = condition.is_none();
-            condition.map(|expr| self.compile_expr(*expr));
+            if let Some(expr) = condition { self.compile_expr(*expr) }
 
             if !default_case {
-                last_cond_jump_operand_ptr = Some(self.ins_len() + 1);
-                self.emit(&cond_jump_bytes);
-                self.compile_expr_list(consequence, true);
-                self.handle_null_return(null_return);
-                jmp_operand_ptrs.push(self.ins_len() + 1);
-                self.emit(&code::Jump.make(u16::MAX));
+                last_cond_jump_operand_ptr = Some(self.emit_jump(&cond_jump_bytes));
+                self.compile_block(consequence, null_return, tail);
+                jmp_operand_ptrs.push(self.emit_jump(&code::JumpW.make(u32::MAX)));
             } else {
-                self.compile_expr_list(consequence, true);
-                self.handle_null_return(null_return);
+                self.compile_block(consequence, null_return, tail);
                 // Any cases that follow the default case will not be compiled because they're unreachable
                 has_default = true;
                 break;
@@ -413,23 +689,13 @@ impl Compiler {
         if !has_default {
             // If no default case was provided, we jump to one that pushes null to the stack
             if let Some(ptr) = last_cond_jump_operand_ptr {
-                self.overwrite_u16(ptr, self.cur_ip());
+                self.patch_jump(ptr);
             }
             self.emit(&code::Null.make());
         }
 
-        let cur_pos = self.cur_ip();
         for jmp_operand_ptr in jmp_operand_ptrs {
-            self.overwrite_u16(jmp_operand_ptr, cur_pos);
-        }
-    }
-
-    fn handle_null_return(&mut self, null_return: bool) {
-        if null_return {
-            self.emit(&code::Null.make());
-        } else {
-            let len = self.ins_len() - 1;
-            self.cur_scope_mut().instructions.truncate(len);
+            self.patch_jump(jmp_operand_ptr);
         }
     }
 
@@ -451,7 +717,7 @@ impl Compiler {
                 range_end,
             } => {
                 let parts = if range_step.is_none() { 2 } else { 3 };
-                range_step.map(|step| self.compile_expr(*step));
+                if let Some(step) = range_step { self.compile_expr(*step) }
                 self.compile_expr(*range_end);
                 self.compile_expr(*range_start);
                 self.emit(&range_builder.make(parts));
@@ -461,6 +727,62 @@ impl Compiler {
     }
 }
 
+/**
+ * Whether an expression might read the variable `name`. Expressions that are harder to follow,
+ * like assignments and switches, are assumed to.
+ */
+fn mentions(expr: &ExprST, name: &str) -> bool {
+    let any = |exprs: &[ExprST]| exprs.iter().any(|expr| mentions(expr, name));
+    match expr {
+        ExprST::Null
+        | ExprST::Newat
+        | ExprST::True
+        | ExprST::False
+        | ExprST::Atom(_)
+        | ExprST::String(_)
+        | ExprST::Integer(_)
+        | ExprST::Float(_) => false,
+        ExprST::Ident(ident) => *ident == name,
+        ExprST::Interpolation(parts) => any(parts),
+        ExprST::TupleLiteral(Former::Literal(exprs)) | ExprST::SetLiteral(Former::Literal(exprs)) => any(exprs),
+        ExprST::TupleLiteral(Former::Range { range_start, range_step, range_end })
+        | ExprST::SetLiteral(Former::Range { range_start, range_step, range_end }) => {
+            mentions(range_start, name)
+                || range_step.as_ref().is_some_and(|step| mentions(step, name))
+                || mentions(range_end, name)
+        }
+        // A function's body has its own scope, and can only see outer variables through its locked params
+        ExprST::Function { locked_params, .. } => locked_params.contains(&name),
+        ExprST::Infix { left, right, .. } | ExprST::ReduceWithOp { left, right, .. } => {
+            mentions(left, name) || mentions(right, name)
+        }
+        ExprST::ReduceWithExpr { apply, left, right } | ExprST::InfixInject { apply, left, right } => {
+            mentions(apply, name) || mentions(left, name) || mentions(right, name)
+        }
+        ExprST::Prefix { right, .. } => mentions(right, name),
+        ExprST::Postfix { left, selector } => {
+            mentions(left, name)
+                || match selector {
+                    Postfix::Call(args) | Postfix::Pick(args) => any(args),
+                    Postfix::Index(index) => mentions(index, name),
+                    Postfix::Range(start, end) => {
+                        start.as_ref().is_some_and(|start| mentions(start, name))
+                            || end.as_ref().is_some_and(|end| mentions(end, name))
+                    }
+                }
+        }
+        ExprST::Ternary { condition, consequence, alternative } => {
+            mentions(condition, name) || mentions(consequence, name) || mentions(alternative, name)
+        }
+        ExprST::Return(expr) => mentions(expr, name),
+        ExprST::TupleLiteral(Former::Iterator { .. })
+        | ExprST::SetLiteral(Former::Iterator { .. })
+        | ExprST::Assign { .. }
+        | ExprST::Switch { .. }
+        | ExprST::Select { .. } => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Bytecode, Compiler};
@@ -489,15 +811,15 @@ mod tests {
         if !equal {
             panic!(
                 "\n\nExpected:\n{}\n\nInstead, got:\n{}\n\n",
-                print_bytes(&expected),
-                print_bytes(&actual)
+                print_bytes(expected),
+                print_bytes(actual)
             )
         }
     }
 
     fn assert_fn_bytes(function: &BaseObject, expected: Vec<u8>) {
         if let Function { ins, .. } = function {
-            assert_bytes(ins, expected);
+            assert_bytes(ins.bytes(), expected);
         } else {
             panic!("not a function");
         }
@@ -554,10 +876,9 @@ mod tests {
                 code::Const::VAL,
                 0,
                 0,
-                code::Const::VAL,
+                code::AddConst::VAL,
                 0,
-                1,
-                code::Add::VAL
+                1
             ])
         );
         assert_eq!(
@@ -590,6 +911,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn interned_constants() {
+        let program = compile_program(r#"1; 2.0; "three"; 1; 2.0; "three"; 2;"#);
+        assert_eq!(
+            program.constants,
+            vec![Integer(1), Float(2.0), String("three".into()), Integer(2)]
+        );
+        assert_eq!(compile("-1 + 1").constants, vec![Integer(-1), Integer(1)]);
+    }
+
+    #[test]
+    fn wide_constants() {
+        let literals: Vec<std::string::String> = (0..70_000).map(|i| i.to_string()).collect();
+        let program = compile_program(&format!("{};", literals.join(";")));
+        assert_eq!(program.constants.len(), 70_000);
+
+        let last_const = program.instuctions.len() - 6;
+        assert_eq!(
+            program.instuctions[last_const..],
+            [code::Const32::VAL, 0, 1, 0x11, 0x6f, code::Pop::VAL]
+        );
+    }
+
     #[test] #[rustfmt::skip]
     fn ternary() {
         assert_bytes(&compile_program("if true ? 1 : 2; 99;").instuctions, vec![
@@ -628,9 +972,7 @@ mod tests {
         let function = &program.constants.last().unwrap();
         assert_fn_bytes(function, vec![
             // 0
-            code::Null::VAL,
-            // 1
-            code::Return::VAL,
+            code::ReturnNull::VAL,
         ]);
 
         let program = compile_program("func() { 1; };");
@@ -641,9 +983,7 @@ mod tests {
             // 3
             code::Pop::VAL,
             // 4
-            code::Null::VAL,
-            // 5
-            code::Return::VAL,
+            code::ReturnNull::VAL,
         ]);
 
         let program = compile_program("func() { 1; 2 };");
@@ -679,10 +1019,98 @@ mod tests {
             // 15
             code::Pop::VAL, // Unreachable
             // 16
-            code::Null::VAL, // Unreachable
+            code::ReturnNull::VAL, // Unreachable
             // 17
-            code::Return::VAL, // Unreachable
-            // 18
+        ]);
+    }
+
+    #[test] #[rustfmt::skip]
+    fn tail_calls() {
+        let program = compile_program("func(g) { g(1); g(2) };");
+        let function = &program.constants.last().unwrap();
+        assert_fn_bytes(function, vec![
+            // 0
+            code::GetLVar0::VAL,
+            // 1
+            code::Const::VAL, 0, 0,
+            // 4
+            code::Call::VAL, 0, 1,
+            // 7
+            code::Pop::VAL,
+            // 8
+            code::GetLVar0::VAL,
+            // 9
+            code::Const::VAL, 0, 1,
+            // 12
+            code::TailCall::VAL, 0, 1,
+            // 15
+            code::Return::VAL,
+        ]);
+
+        let program = compile_program("func(g) { return g(); };");
+        let function = &program.constants.last().unwrap();
+        assert_fn_bytes(function, vec![
+            // 0
+            code::GetLVar0::VAL,
+            // 1
+            code::TailCall::VAL, 0, 0,
+            // 4
+            code::Return::VAL,
+            // 5
+            code::Pop::VAL, // Unreachable
+            // 6
+            code::ReturnNull::VAL, // Unreachable
+        ]);
+
+        // Calls made at the top level of the program aren't tail calls
+        let program = compile_program("g = 0; return g();");
+        assert!(program.instuctions.contains(&code::Call::VAL));
+        assert!(!program.instuctions.contains(&code::TailCall::VAL));
+    }
+
+    #[test] #[rustfmt::skip]
+    fn self_updates() {
+        assert_bytes(&compile_program("s = {}; s = s with 1; s[0] = 2;").instuctions, vec![
+            // 0
+            code::ToSet::VAL, 0, 0,
+            // 3
+            code::SetGVar::VAL, 0, 0,
+            // 6
+            code::Pop::VAL,
+            // 7
+            code::GetGVar::VAL, 0, 0,
+            // 10
+            code::Const::VAL, 0, 0,
+            // 13
+            code::WithGVar::VAL, 0, 0,
+            // 16
+            code::Pop::VAL,
+            // 17
+            code::GetGVar::VAL, 0, 0,
+            // 20
+            code::Const::VAL, 0, 1,
+            // 23
+            code::Const::VAL, 0, 2,
+            // 26
+            code::SetIndexGVar::VAL, 0, 0,
+            // 29
+            code::Pop::VAL,
+        ]);
+
+        // Assigning to a new local from a global can't update in place
+        let program = compile_program("s = {}; func() { s = s less 1 };");
+        let function = &program.constants.last().unwrap();
+        assert_fn_bytes(function, vec![
+            // 0
+            code::GetGVar::VAL, 0, 0,
+            // 3
+            code::Const::VAL, 0, 0,
+            // 6
+            code::Less::VAL,
+            // 7
+            code::SetLVar::VAL, 0, 0,
+            // 10
+            code::Return::VAL,
         ]);
     }
 }
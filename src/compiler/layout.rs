@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use bytes::{BufMut, BytesMut};
+
+use crate::code::code::{is_jump, narrow_jump, JUMP_WIDTHS};
+use crate::code::debug::{decode, lookup};
+
+/*
+ * While a function is being compiled, every jump is emitted in its wide form so that it can be
+ * backpatched with any target. Once the function is complete, its instructions are decoded into
+ * a list where each jump refers to the index of the instruction it lands on, rather than a byte
+ * offset. That list can then be rearranged freely and laid back out into bytes, at which point
+ * each jump picks the narrowest form that can reach its target.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Op {
+    pub code: u8,
+    /** For jumps, this is the index of the target instruction (which may be one past the end) */
+    pub operands: Vec<usize>,
+}
+
+impl Op {
+    pub fn new(code: u8, operands: Vec<usize>) -> Self {
+        Self { code, operands }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        is_jump(self.code)
+    }
+
+    pub fn target(&self) -> Option<usize> {
+        if self.is_jump() {
+            self.operands.first().copied()
+        } else {
+            None
+        }
+    }
+}
+
+/** Decodes a byte stream, turning every jump target into an instruction index */
+pub fn unpack(bytes: &[u8]) -> Vec<Op> {
+    let instructions = decode(bytes);
+    let indices: HashMap<usize, usize> = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, ins)| (ins.pos, index))
+        .chain([(bytes.len(), instructions.len())])
+        .collect();
+
+    instructions
+        .into_iter()
+        .map(|ins| {
+            if is_jump(ins.op) {
+                let target = *indices
+                    .get(&ins.operands[0])
+                    .unwrap_or_else(|| panic!("Jump at {} lands mid-instruction", ins.pos));
+                Op::new(narrow_jump(ins.op), vec![target])
+            } else {
+                Op::new(ins.op, ins.operands)
+            }
+        })
+        .collect()
+}
+
+/**
+ * Lays instructions back out into bytes. Jumps start out narrow and are widened one at a time
+ * until every target is in reach, since widening a jump can push other targets further away.
+ */
+pub fn pack(ops: &[Op]) -> BytesMut {
+    let mut wide = vec![false; ops.len()];
+    let offsets = loop {
+        let offsets = layout(ops, &wide);
+        let mut changed = false;
+        for (index, op) in ops.iter().enumerate() {
+            if let Some(target) = op.target() {
+                if !wide[index] && offsets[target] > u16::MAX as usize {
+                    wide[index] = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break offsets;
+        }
+    };
+
+    let total = *offsets.last().unwrap();
+    if total > u32::MAX as usize {
+        panic!("Function is too large to compile ({} bytes of bytecode)", total);
+    }
+
+    let mut bytes = BytesMut::with_capacity(total);
+    for (index, op) in ops.iter().enumerate() {
+        if let Some(target) = op.target() {
+            if wide[index] {
+                bytes.put_u8(widen(op.code));
+                bytes.put_u32(offsets[target] as u32);
+            } else {
+                bytes.put_u8(op.code);
+                bytes.put_u16(offsets[target] as u16);
+            }
+            continue;
+        }
+
+        bytes.put_u8(op.code);
+        for (size, operand) in lookup(op.code).unwrap().0.iter().zip(op.operands.iter()) {
+            match *size {
+                2 => bytes.put_u16(*operand as u16),
+                4 => bytes.put_u32(*operand as u32),
+                _ => unreachable!(),
+            }
+        }
+    }
+    bytes
+}
+
+/**
+ * Drops removed instructions and relocates every jump. A jump to a removed instruction lands on
+ * the next instruction that survives instead.
+ */
+pub fn compact(ops: Vec<Op>, removed: &[bool]) -> Vec<Op> {
+    let mut new_indices = vec![0; ops.len() + 1];
+    let mut next_index = removed.iter().filter(|r| !**r).count();
+    new_indices[ops.len()] = next_index;
+    for index in (0..ops.len()).rev() {
+        if !removed[index] {
+            next_index -= 1;
+        }
+        new_indices[index] = next_index;
+    }
+
+    ops.into_iter()
+        .zip(removed.iter())
+        .filter(|(_, removed)| !**removed)
+        .map(|(mut op, _)| {
+            if let Some(target) = op.target() {
+                op.operands[0] = new_indices[target];
+            }
+            op
+        })
+        .collect()
+}
+
+/** Byte offset of every instruction, plus the offset of the end of the function */
+fn layout(ops: &[Op], wide: &[bool]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(ops.len() + 1);
+    let mut pos = 0;
+    for (op, is_wide) in ops.iter().zip(wide.iter()) {
+        offsets.push(pos);
+        pos += match (op.is_jump(), is_wide) {
+            (true, true) => 5,
+            (true, false) => 3,
+            _ => 1 + lookup(op.code).unwrap().0.iter().sum::<usize>(),
+        };
+    }
+    offsets.push(pos);
+    offsets
+}
+
+fn widen(code: u8) -> u8 {
+    JUMP_WIDTHS
+        .iter()
+        .find(|(narrow, _)| *narrow == code)
+        .map_or(code, |(_, wide)| *wide)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack, unpack, Op};
+    use crate::code::code::{self, OpCode};
+
+    #[test] #[rustfmt::skip]
+    fn narrows_jumps() {
+        let bytes = [
+            code::True::VAL,
+            code::JumpNotTrueW::VAL, 0, 0, 0, 11,
+            code::JumpW::VAL, 0, 0, 0, 0,
+            code::Null::VAL,
+        ];
+        assert_eq!(
+            unpack(&bytes),
+            vec![
+                Op::new(code::True::VAL, vec![]),
+                Op::new(code::JumpNotTrue::VAL, vec![3]),
+                Op::new(code::Jump::VAL, vec![0]),
+                Op::new(code::Null::VAL, vec![]),
+            ]
+        );
+        assert_eq!(
+            pack(&unpack(&bytes))[..],
+            [
+                code::True::VAL,
+                code::JumpNotTrue::VAL, 0, 7,
+                code::Jump::VAL, 0, 0,
+                code::Null::VAL,
+            ]
+        );
+    }
+
+    #[test]
+    fn widens_far_jumps() {
+        // A forward jump over 30,000 constants (90,000 bytes) can't be reached by a u16
+        let mut ops = vec![Op::new(code::JumpNotTrue::VAL, vec![30_002])];
+        ops.extend((0..30_000).map(|_| Op::new(code::Const::VAL, vec![0])));
+        ops.push(Op::new(code::Jump::VAL, vec![0]));
+        ops.push(Op::new(code::Null::VAL, vec![]));
+
+        let bytes = pack(&ops);
+        assert_eq!(bytes[..5], [code::JumpNotTrueW::VAL, 0, 1, 0x5f, 0x98]);
+        // The backwards jump to the start is still in reach
+        let back_jump = 5 + 90_000;
+        assert_eq!(bytes[back_jump..back_jump + 3], [code::Jump::VAL, 0, 0]);
+        assert_eq!(unpack(&bytes), ops);
+    }
+}
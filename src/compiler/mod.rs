@@ -1,2 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod compiler;
+pub mod layout;
+pub mod optimizer;
+pub mod peephole;
+pub mod specialize;
 pub mod symbols;
@@ -0,0 +1,445 @@
+use crate::object::object::float_eq;
+use crate::parser::ast::{
+    BinOp, Case, ExprST, Former, IteratorST, IteratorType, Postfix, PreOp, Program, Stmt, LHS,
+};
+
+/*
+ * AST-level optimizations that run between parsing and code generation:
+ * - Arithmetic and comparisons between numeric literals are folded into a single literal
+ * - Ternaries with a literal condition are replaced by the branch that would be taken
+ * - Expressions that follow a `return` in the same block can never run, so they're dropped
+ * - Cases that follow a `~` default case can never be matched, so they're dropped
+ * - Literals whose value would just be popped off the stack again are dropped
+ *
+ * Folding never happens when the operation would fail at runtime (eg: division by zero or an
+ * integer overflow), so that the error still surfaces when the program is run.
+ */
+
+pub fn optimize_program(program: Program) -> Program {
+    Program {
+        name: program.name,
+        // The last expression of a program is its result, so it's kept even if it has no effect
+        expressions: optimize_block(program.expressions, false),
+    }
+}
+
+pub fn optimize_expr(node: ExprST) -> ExprST {
+    match node {
+        ExprST::Infix { op, left, right } => {
+            let left = optimize_expr(*left);
+            let right = optimize_expr(*right);
+            match fold_infix(&op, &left, &right) {
+                Some(folded) => folded,
+                None => ExprST::Infix {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+            }
+        }
+        ExprST::Prefix { op, right } => {
+            let right = optimize_expr(*right);
+            match (&op, &right) {
+                (PreOp::Id, ExprST::Integer(_) | ExprST::Float(_)) => right,
+                (PreOp::Negate, &ExprST::Integer(val)) if val != i64::MIN => ExprST::Integer(-val),
+                (PreOp::Negate, &ExprST::Float(val)) => ExprST::Float(-val),
+                (PreOp::Not, ExprST::True) => ExprST::False,
+                (PreOp::Not, ExprST::False) => ExprST::True,
+                _ => ExprST::Prefix {
+                    op,
+                    right: Box::new(right),
+                },
+            }
+        }
+        ExprST::Ternary {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            let condition = optimize_expr(*condition);
+            match literal_truthiness(&condition) {
+                Some(true) => optimize_expr(*consequence),
+                Some(false) => optimize_expr(*alternative),
+                None => ExprST::Ternary {
+                    condition: Box::new(condition),
+                    consequence: Box::new(optimize_expr(*consequence)),
+                    alternative: Box::new(optimize_expr(*alternative)),
+                },
+            }
+        }
+        ExprST::Switch { input, cases } => ExprST::Switch {
+            input: input.map(optimize_boxed),
+            cases: optimize_cases(cases),
+        },
+        ExprST::Function {
+            req_params,
+            opt_params,
+            locked_params,
+            body,
+            null_return,
+        } => ExprST::Function {
+            req_params,
+            opt_params,
+            locked_params,
+            body: optimize_block(body, null_return),
+            null_return,
+        },
+        ExprST::TupleLiteral(former) => ExprST::TupleLiteral(optimize_former(former)),
+        ExprST::SetLiteral(former) => ExprST::SetLiteral(optimize_former(former)),
+        ExprST::Postfix { left, selector } => ExprST::Postfix {
+            left: optimize_boxed(left),
+            selector: optimize_postfix(selector),
+        },
+        ExprST::Assign { left, right } => ExprST::Assign {
+            left: optimize_lhs(left),
+            right: optimize_boxed(right),
+        },
+        ExprST::ReduceWithOp { op, left, right } => ExprST::ReduceWithOp {
+            op,
+            left: optimize_boxed(left),
+            right: optimize_boxed(right),
+        },
+        ExprST::ReduceWithExpr { apply, left, right } => ExprST::ReduceWithExpr {
+            apply: optimize_boxed(apply),
+            left: optimize_boxed(left),
+            right: optimize_boxed(right),
+        },
+        ExprST::InfixInject { apply, left, right } => ExprST::InfixInject {
+            apply: optimize_boxed(apply),
+            left: optimize_boxed(left),
+            right: optimize_boxed(right),
+        },
+        ExprST::Select { op, iterator } => ExprST::Select {
+            op,
+            iterator: optimize_iterator(iterator),
+        },
+        ExprST::Interpolation(parts) => ExprST::Interpolation(parts.into_iter().map(optimize_expr).collect()),
+        ExprST::Return(expr) => ExprST::Return(optimize_boxed(expr)),
+        node => node,
+    }
+}
+
+/** Optimizes a boxed node in place, reusing its allocation */
+fn optimize_boxed(mut node: Box<ExprST>) -> Box<ExprST> {
+    *node = optimize_expr(std::mem::replace(&mut *node, ExprST::Null));
+    node
+}
+
+fn optimize_list(nodes: Vec<ExprST>) -> Vec<ExprST> {
+    nodes.into_iter().map(optimize_expr).collect()
+}
+
+/**
+ * Optimizes a sequence of expressions where every value except the last is popped. If
+ * `discard_last` is set, the value of the last expression is also popped.
+ */
+fn optimize_block(stmts: Vec<Stmt>, discard_last: bool) -> Vec<Stmt> {
+    let mut block: Vec<Stmt> = vec![];
+    for stmt in stmts.into_iter() {
+        let expr = optimize_expr(stmt.expr);
+        let returns = matches!(expr, ExprST::Return(_));
+        block.push(Stmt { line: stmt.line, expr });
+        if returns {
+            break;
+        }
+    }
+
+    let last = block.len().saturating_sub(1);
+    block
+        .into_iter()
+        .enumerate()
+        .filter(|(index, stmt)| (*index == last && !discard_last) || !is_pure(&stmt.expr))
+        .map(|(_, stmt)| stmt)
+        .collect()
+}
+
+fn optimize_cases(cases: Vec<Case>) -> Vec<Case> {
+    let mut optimized = vec![];
+    for case in cases.into_iter() {
+        let default_case = case.condition.is_none();
+        optimized.push(Case {
+            condition: case.condition.map(optimize_boxed),
+            consequence: optimize_block(case.consequence, case.null_return),
+            null_return: case.null_return,
+        });
+        if default_case {
+            break;
+        }
+    }
+    optimized
+}
+
+fn optimize_former(former: Former) -> Former {
+    match former {
+        Former::Literal(exprs) => Former::Literal(optimize_list(exprs)),
+        Former::Range {
+            range_start,
+            range_step,
+            range_end,
+        } => Former::Range {
+            range_start: optimize_boxed(range_start),
+            range_step: range_step.map(optimize_boxed),
+            range_end: optimize_boxed(range_end),
+        },
+        Former::Iterator { iterator, output } => Former::Iterator {
+            iterator: optimize_iterator(iterator),
+            output: optimize_boxed(output),
+        },
+    }
+}
+
+fn optimize_iterator(iterator: IteratorST) -> IteratorST {
+    IteratorST {
+        iterators: iterator
+            .iterators
+            .into_iter()
+            .map(|it| match it {
+                IteratorType::In { list, expr } => IteratorType::In {
+                    list,
+                    expr: optimize_boxed(expr),
+                },
+                other => other,
+            })
+            .collect(),
+        filter: optimize_list(iterator.filter),
+    }
+}
+
+fn optimize_postfix(selector: Postfix) -> Postfix {
+    match selector {
+        Postfix::Call(args) => Postfix::Call(optimize_list(args)),
+        Postfix::Index(index) => Postfix::Index(optimize_boxed(index)),
+        Postfix::Range(start, end) => Postfix::Range(start.map(optimize_boxed), end.map(optimize_boxed)),
+        Postfix::Pick(args) => Postfix::Pick(optimize_list(args)),
+    }
+}
+
+fn optimize_lhs(lhs: LHS) -> LHS {
+    match lhs {
+        LHS::Ident { target, selectors } => LHS::Ident {
+            target,
+            selectors: selectors.into_iter().map(optimize_postfix).collect(),
+        },
+        LHS::List(items) => LHS::List(items.into_iter().map(optimize_lhs).collect()),
+        LHS::Tilde => LHS::Tilde,
+    }
+}
+
+/** Expressions which only push a value, and can't fail or have side effects */
+fn is_pure(expr: &ExprST) -> bool {
+    match expr {
+        ExprST::Null
+        | ExprST::True
+        | ExprST::False
+        | ExprST::Integer(_)
+        | ExprST::Float(_)
+        | ExprST::String(_)
+        | ExprST::Atom(_) => true,
+        ExprST::TupleLiteral(Former::Literal(exprs)) | ExprST::SetLiteral(Former::Literal(exprs)) => {
+            exprs.iter().all(is_pure)
+        }
+        _ => false,
+    }
+}
+
+/** The truthiness of a literal, which matches `ObjectOps::truthy` for the same value */
+fn literal_truthiness(expr: &ExprST) -> Option<bool> {
+    match expr {
+        ExprST::True => Some(true),
+        ExprST::False | ExprST::Null => Some(false),
+        ExprST::Integer(val) => Some(*val != 0),
+        ExprST::Float(val) => Some(*val != 0.0),
+        ExprST::String(val) => Some(!val.is_empty()),
+        _ => None,
+    }
+}
+
+fn bool_expr<'a>(value: bool) -> ExprST<'a> {
+    if value {
+        ExprST::True
+    } else {
+        ExprST::False
+    }
+}
+
+fn fold_infix<'a>(op: &BinOp, left: &ExprST<'a>, right: &ExprST<'a>) -> Option<ExprST<'a>> {
+    match (left, right) {
+        (&ExprST::Integer(l), &ExprST::Integer(r)) => fold_int(op, l, r),
+        (&ExprST::Integer(l), &ExprST::Float(r)) => fold_float(op, l as f64, r),
+        (&ExprST::Float(l), &ExprST::Integer(r)) => fold_float(op, l, r as f64),
+        (&ExprST::Float(l), &ExprST::Float(r)) => fold_float(op, l, r),
+        _ => None,
+    }
+    .or_else(|| fold_equality(op, left, right))
+}
+
+fn fold_int<'a>(op: &BinOp, l: i64, r: i64) -> Option<ExprST<'a>> {
+    match op {
+        BinOp::Add => l.checked_add(r).map(ExprST::Integer),
+        BinOp::Subtract => l.checked_sub(r).map(ExprST::Integer),
+        BinOp::Mult => l.checked_mul(r).map(ExprST::Integer),
+        BinOp::Div => Some(ExprST::Float(l as f64 / r as f64)),
+        BinOp::IntDiv if r != 0 => l.checked_div(r).map(ExprST::Integer),
+        BinOp::Exp => u32::try_from(r)
+            .ok()
+            .and_then(|r| l.checked_pow(r))
+            .map(ExprST::Integer),
+        BinOp::LT => Some(bool_expr(l < r)),
+        BinOp::LTEQ => Some(bool_expr(l <= r)),
+        BinOp::GT => Some(bool_expr(l > r)),
+        BinOp::GTEQ => Some(bool_expr(l >= r)),
+        BinOp::EQ => Some(bool_expr(l == r)),
+        BinOp::NEQ => Some(bool_expr(l != r)),
+        _ => None,
+    }
+}
+
+fn fold_float<'a>(op: &BinOp, l: f64, r: f64) -> Option<ExprST<'a>> {
+    match op {
+        BinOp::Add => Some(ExprST::Float(l + r)),
+        BinOp::Subtract => Some(ExprST::Float(l - r)),
+        BinOp::Mult => Some(ExprST::Float(l * r)),
+        BinOp::Div if r != 0.0 => Some(ExprST::Float(l / r)),
+        BinOp::Exp => Some(ExprST::Float(l.powf(r))),
+        BinOp::LT => Some(bool_expr(l < r)),
+        BinOp::LTEQ => Some(bool_expr(l <= r)),
+        BinOp::GT => Some(bool_expr(l > r)),
+        BinOp::GTEQ => Some(bool_expr(l >= r)),
+        // Integers and floats are never equal to each other at runtime, since they're
+        // different types of object, so these are only folded for two floats.
+        _ => None,
+    }
+}
+
+fn fold_equality<'a>(op: &BinOp, left: &ExprST<'a>, right: &ExprST<'a>) -> Option<ExprST<'a>> {
+    let equal = match (left, right) {
+        (ExprST::Null, ExprST::Null)
+        | (ExprST::True, ExprST::True)
+        | (ExprST::False, ExprST::False) => true,
+        (ExprST::Integer(l), ExprST::Integer(r)) => l == r,
+        // Folded the same way `==` compares floats at runtime, where NaN is equal to itself
+        (ExprST::Float(l), ExprST::Float(r)) => float_eq(*l, *r),
+        (ExprST::String(l), ExprST::String(r)) => l == r,
+        (
+            ExprST::Null | ExprST::True | ExprST::False | ExprST::Integer(_) | ExprST::Float(_) | ExprST::String(_),
+            ExprST::Null | ExprST::True | ExprST::False | ExprST::Integer(_) | ExprST::Float(_) | ExprST::String(_),
+        ) => false,
+        _ => return None,
+    };
+    match op {
+        BinOp::EQ => Some(bool_expr(equal)),
+        BinOp::NEQ => Some(bool_expr(!equal)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{optimize_expr, optimize_program};
+    use crate::code::code::{self, OpCode};
+    use crate::compiler::compiler::{Bytecode, Compiler};
+    use crate::object::object::BaseObject::*;
+    use crate::object::object::Object;
+    use crate::parser::ast::ExprST;
+    use crate::parser::parser;
+    use crate::vm::vm::VM;
+
+    fn compile_optimized(input: &str) -> Bytecode {
+        let wrapped_input = format!("program :any; {}", input);
+        let mut c = Compiler::new();
+        let program = parser::parse_from_program(&wrapped_input).unwrap();
+        c.compile_program(optimize_program(program));
+        c.finish()
+    }
+
+    #[test]
+    fn folds_arithmetic() {
+        let program = compile_optimized("1 + 2 * 3;");
+        assert_eq!(program.instuctions[..], [code::Const::VAL, 0, 0, code::Pop::VAL]);
+        assert_eq!(program.constants, vec![Integer(7)]);
+
+        let program = compile_optimized("(2 ** 3) / 4 - 0.5;");
+        assert_eq!(program.constants, vec![Float(1.5)]);
+
+        let program = compile_optimized("-(3 - 5);");
+        assert_eq!(program.constants, vec![Integer(2)]);
+    }
+
+    #[test]
+    fn folds_comparisons() {
+        let program = compile_optimized("3 >= 4;");
+        assert_eq!(program.instuctions[..], [code::False::VAL, code::Pop::VAL]);
+
+        let program = compile_optimized(r#""a" == "a";"#);
+        assert_eq!(program.instuctions[..], [code::True::VAL, code::Pop::VAL]);
+
+        let program = compile_optimized("1 == 1.0;");
+        assert_eq!(program.instuctions[..], [code::False::VAL, code::Pop::VAL]);
+
+        let program = compile_optimized("!(1 < 2);");
+        assert_eq!(program.instuctions[..], [code::False::VAL, code::Pop::VAL]);
+    }
+
+    #[test]
+    fn folds_float_equality_like_runtime() {
+        let nan = "(1.0e308 * 10.0 - 1.0e308 * 10.0)";
+        let inputs =
+            [format!("{nan} == {nan}"), format!("{nan} != {nan}"), "0.0 == -0.0".to_owned(), "0.0 != -0.0".to_owned()];
+        for input in inputs {
+            let folded = match optimize_expr(parser::parse_from_expr(&input).unwrap()) {
+                ExprST::True => Object::True,
+                ExprST::False => Object::False,
+                other => panic!("{} wasn't folded, it's {:?}", input, other),
+            };
+
+            let mut c = Compiler::new();
+            c.compile_expr(parser::parse_from_expr(&input).unwrap());
+            let mut vm = VM::new(c.finish());
+            vm.run().unwrap();
+            assert_eq!(Some(&folded), vm.peek_top(), "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn keeps_failing_operations() {
+        let program = compile_optimized("1 div 0;");
+        assert_eq!(program.constants, vec![Integer(1), Integer(0)]);
+
+        let program = compile_optimized("9223372036854775807 + 1;");
+        assert_eq!(program.constants, vec![Integer(i64::MAX), Integer(1)]);
+    }
+
+    #[test]
+    fn folds_ternaries() {
+        let program = compile_optimized("if 1 < 2 ? 10 : 20;");
+        assert_eq!(program.instuctions[..], [code::Const::VAL, 0, 0, code::Pop::VAL]);
+        assert_eq!(program.constants, vec![Integer(10)]);
+
+        let program = compile_optimized(r#"if "" ? 10 : 20;"#);
+        assert_eq!(program.constants, vec![Integer(20)]);
+    }
+
+    #[test] #[rustfmt::skip]
+    fn drops_dead_code() {
+        let program = compile_optimized("f = func(a) { 1; a; return a; a + 1 };");
+        let Function { ins, .. } = &program.constants[0] else { panic!("not a function") };
+        assert_eq!(ins.bytes()[..], [
+            code::GetLVar0::VAL,
+            code::Pop::VAL,
+            code::GetLVar0::VAL,
+            code::Return::VAL,
+            code::Return::VAL,
+        ]);
+
+        let switch = optimize_expr(parser::parse_from_expr("case { a : 1, ~ : 2, b : 3 }").unwrap());
+        let ExprST::Switch { cases, .. } = switch else { panic!("not a switch") };
+        assert_eq!(cases.len(), 2);
+    }
+
+    #[test]
+    fn keeps_program_result() {
+        let program = compile_optimized("1; 2; 3;");
+        assert_eq!(program.instuctions[..], [code::Const::VAL, 0, 0, code::Pop::VAL]);
+        assert_eq!(program.constants, vec![Integer(3)]);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+
+use crate::code::code::{self, OpCode};
+
+use super::layout::{compact, Op};
+
+/*
+ * Small rewrites over a function's instructions, run after code generation (see `layout` for how
+ * jump targets are tracked while instructions move around):
+ * - A value that's pushed and then immediately popped is never pushed in the first place, unless
+ *   it's the last instruction. The main program's last `Pop` hands over the program's result.
+ * - A jump that lands on an unconditional jump goes straight to the final destination
+ * - `Not` followed by `JumpNotTrue` becomes a single `JumpTrue`, when the value being negated is
+ *   known to be a boolean. `Not` rejects anything else, while `JumpTrue` would accept it.
+ * - An unconditional jump to the very next instruction is removed
+ *
+ * The rules are applied until none of them make any more changes.
+ */
+
+pub fn peephole(mut ops: Vec<Op>) -> Vec<Op> {
+    loop {
+        let (next_ops, changed) = peephole_pass(ops);
+        ops = next_ops;
+        if !changed {
+            return ops;
+        }
+    }
+}
+
+/** Instructions that only push a value onto the stack, with no other effects */
+fn is_push(op: &Op) -> bool {
+    matches!(
+        op.code,
+        code::Const::VAL
+            | code::Const32::VAL
+            | code::Null::VAL
+            | code::True::VAL
+            | code::False::VAL
+            | code::GetGVar::VAL
+            | code::GetLVar::VAL
+    )
+}
+
+/** Instructions that always push a boolean */
+fn pushes_boolean(op: &Op) -> bool {
+    matches!(
+        op.code,
+        code::True::VAL
+            | code::False::VAL
+            | code::Not::VAL
+            | code::Eq::VAL
+            | code::Neq::VAL
+            | code::Lt::VAL
+            | code::Lteq::VAL
+            | code::In::VAL
+            | code::Notin::VAL
+    )
+}
+
+fn peephole_pass(mut ops: Vec<Op>) -> (Vec<Op>, bool) {
+    let targets: HashSet<usize> = ops.iter().filter_map(Op::target).collect();
+    let mut removed = vec![false; ops.len()];
+    let mut changed = false;
+
+    let mut index = 0;
+    while index < ops.len() {
+        if let Some(target) = ops[index].target() {
+            let final_target = follow_jumps(&ops, target);
+            if final_target != target {
+                ops[index].operands[0] = final_target;
+                changed = true;
+            }
+        }
+
+        let op = &ops[index];
+        let next = ops.get(index + 1);
+        let next_is_target = targets.contains(&(index + 1));
+
+        if op.code == code::Jump::VAL && op.target() == Some(index + 1) {
+            removed[index] = true;
+        } else if is_push(op)
+            && !next_is_target
+            && index + 2 < ops.len()
+            && next.is_some_and(|n| n.code == code::Pop::VAL)
+        {
+            removed[index] = true;
+            removed[index + 1] = true;
+            index += 1;
+        } else if op.code == code::Not::VAL
+            && index > 0
+            && pushes_boolean(&ops[index - 1])
+            && !targets.contains(&index)
+            && !next_is_target
+            && next.is_some_and(|n| n.code == code::JumpNotTrue::VAL)
+        {
+            removed[index] = true;
+            ops[index + 1].code = code::JumpTrue::VAL;
+        }
+
+        index += 1;
+    }
+
+    changed |= removed.contains(&true);
+    (compact(ops, &removed), changed)
+}
+
+/** Follows a chain of unconditional jumps to where it finally ends up */
+fn follow_jumps(ops: &[Op], mut target: usize) -> usize {
+    // The step limit guards against jumps that loop back onto themselves
+    for _ in 0..ops.len() {
+        match ops.get(target) {
+            Some(op) if op.code == code::Jump::VAL => target = op.operands[0],
+            _ => break,
+        }
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::peephole;
+    use crate::code::code::{self, OpCode};
+    use crate::compiler::compiler::{Bytecode, Compiler};
+    use crate::compiler::layout::Op;
+    use crate::parser::parser;
+
+    fn op(code: u8, operands: Vec<usize>) -> Op {
+        Op::new(code, operands)
+    }
+
+    fn compile_optimized(input: &str) -> Bytecode {
+        let wrapped_input = format!("program :any; {}", input);
+        let mut c = Compiler::with_optimizations(true);
+        c.compile_program(parser::parse_from_program(&wrapped_input).unwrap());
+        c.finish()
+    }
+
+    #[test]
+    fn removes_push_pop() {
+        let ops = vec![
+            op(code::GetGVar::VAL, vec![0]),
+            op(code::Pop::VAL, vec![]),
+            op(code::True::VAL, vec![]),
+        ];
+        assert_eq!(peephole(ops), vec![op(code::True::VAL, vec![])]);
+
+        // The last pop is the main program's result, so it's kept
+        let ops = vec![op(code::True::VAL, vec![]), op(code::Pop::VAL, vec![])];
+        assert_eq!(peephole(ops.clone()), ops);
+
+        // The pop is a jump target, so it has to stay where it is
+        let ops = vec![
+            op(code::JumpNotTrue::VAL, vec![2]),
+            op(code::Null::VAL, vec![]),
+            op(code::Pop::VAL, vec![]),
+        ];
+        assert_eq!(peephole(ops.clone()), ops);
+    }
+
+    #[test]
+    fn threads_jumps() {
+        let ops = vec![
+            op(code::JumpNotTrue::VAL, vec![2]),
+            op(code::Null::VAL, vec![]),
+            op(code::Jump::VAL, vec![4]),
+            op(code::True::VAL, vec![]),
+            op(code::False::VAL, vec![]),
+        ];
+        assert_eq!(
+            peephole(ops),
+            vec![
+                op(code::JumpNotTrue::VAL, vec![4]),
+                op(code::Null::VAL, vec![]),
+                op(code::Jump::VAL, vec![4]),
+                op(code::True::VAL, vec![]),
+                op(code::False::VAL, vec![]),
+            ]
+        );
+
+        // Jumps that loop forever are left alone
+        let ops = vec![op(code::Jump::VAL, vec![0])];
+        assert_eq!(peephole(ops.clone()), ops);
+    }
+
+    #[test]
+    fn inverts_not_jumps() {
+        let ops = vec![
+            op(code::GetGVar::VAL, vec![0]),
+            op(code::GetGVar::VAL, vec![1]),
+            op(code::Eq::VAL, vec![]),
+            op(code::Not::VAL, vec![]),
+            op(code::JumpNotTrue::VAL, vec![6]),
+            op(code::Null::VAL, vec![]),
+            op(code::True::VAL, vec![]),
+        ];
+        assert_eq!(
+            peephole(ops),
+            vec![
+                op(code::GetGVar::VAL, vec![0]),
+                op(code::GetGVar::VAL, vec![1]),
+                op(code::Eq::VAL, vec![]),
+                op(code::JumpTrue::VAL, vec![5]),
+                op(code::Null::VAL, vec![]),
+                op(code::True::VAL, vec![]),
+            ]
+        );
+
+        // A variable might not hold a boolean, which `Not` has to reject
+        let ops = vec![
+            op(code::GetGVar::VAL, vec![0]),
+            op(code::Not::VAL, vec![]),
+            op(code::JumpNotTrue::VAL, vec![4]),
+            op(code::Null::VAL, vec![]),
+            op(code::True::VAL, vec![]),
+        ];
+        assert_eq!(peephole(ops.clone()), ops);
+    }
+
+    #[test]
+    fn removes_jump_to_next() {
+        let ops = vec![
+            op(code::JumpNotTrue::VAL, vec![1]),
+            op(code::Jump::VAL, vec![2]),
+            op(code::Null::VAL, vec![]),
+        ];
+        assert_eq!(
+            peephole(ops),
+            vec![
+                op(code::JumpNotTrue::VAL, vec![1]),
+                op(code::Null::VAL, vec![]),
+            ]
+        );
+    }
+
+    #[test] #[rustfmt::skip]
+    fn optimized_program() {
+        let program = compile_optimized("x = 1; y = 2; x; if not (x == y) ? x : y;");
+        assert_eq!(program.instuctions[..], [
+            // 0
+            code::Const::VAL, 0, 0,
+            // 3
+            code::SetGVar::VAL, 0, 0,
+            // 6
+            code::Pop::VAL,
+            // 7
+            code::Const::VAL, 0, 1,
+            // 10
+            code::SetGVar::VAL, 0, 1,
+            // 13
+            code::Pop::VAL,
+            // 14
+            code::GetGVar::VAL, 0, 0,
+            // 17
+            code::GetGVar::VAL, 0, 1,
+            // 20
+            code::Eq::VAL,
+            // 21
+            code::JumpTrue::VAL, 0, 30,
+            // 24
+            code::GetGVar::VAL, 0, 0,
+            // 27
+            code::Jump::VAL, 0, 33,
+            // 30
+            code::GetGVar::VAL, 0, 1,
+            // 33
+            code::Pop::VAL,
+        ]);
+    }
+}
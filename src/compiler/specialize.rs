@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use crate::code::code::{self, OpCode};
+use crate::object::object::BaseObject;
+
+use super::layout::{compact, Op};
+
+/*
+ * Replaces common instruction sequences with specialized opcodes that do the same work in a
+ * single dispatch. This runs on every function once its code is complete, after the peephole
+ * pass when optimizations are enabled:
+ * - `GetLVar n` for the first four locals becomes `GetLVar0` through `GetLVar3`
+ * - `GetLVar n, Const 1, Add, SetLVar n` becomes `IncLocal n`
+ * - `Const k, Add` becomes `AddConst k`
+ * - `Lt, JumpNotTrue` becomes `LtJumpNotTrue`
+ * - `Null, Return` becomes `ReturnNull`
+ *
+ * Instructions are only merged when no jump lands in the middle of the sequence.
+ */
+
+const GET_LVAR_N: [u8; 4] = [
+    code::GetLVar0::VAL,
+    code::GetLVar1::VAL,
+    code::GetLVar2::VAL,
+    code::GetLVar3::VAL,
+];
+
+pub fn specialize(mut ops: Vec<Op>, constants: &[BaseObject]) -> Vec<Op> {
+    let targets: HashSet<usize> = ops.iter().filter_map(Op::target).collect();
+    let mut removed = vec![false; ops.len()];
+
+    let mut index = 0;
+    while index < ops.len() {
+        let sequence = |codes: &[u8]| {
+            codes.iter().enumerate().all(|(offset, code)| {
+                ops.get(index + offset).is_some_and(|op| op.code == *code)
+                    && (offset == 0 || !targets.contains(&(index + offset)))
+            })
+        };
+
+        let (replacement, length) = if sequence(&[
+            code::GetLVar::VAL,
+            code::Const::VAL,
+            code::Add::VAL,
+            code::SetLVar::VAL,
+        ]) && ops[index].operands == ops[index + 3].operands
+            && constants.get(ops[index + 1].operands[0]) == Some(&BaseObject::Integer(1))
+        {
+            (Op::new(code::IncLocal::VAL, ops[index].operands.clone()), 4)
+        } else if sequence(&[code::Const::VAL, code::Add::VAL]) {
+            (Op::new(code::AddConst::VAL, ops[index].operands.clone()), 2)
+        } else if sequence(&[code::Lt::VAL, code::JumpNotTrue::VAL]) {
+            (Op::new(code::LtJumpNotTrue::VAL, ops[index + 1].operands.clone()), 2)
+        } else if sequence(&[code::Null::VAL, code::Return::VAL]) {
+            (Op::new(code::ReturnNull::VAL, vec![]), 2)
+        } else if ops[index].code == code::GetLVar::VAL && ops[index].operands[0] < GET_LVAR_N.len() {
+            (Op::new(GET_LVAR_N[ops[index].operands[0]], vec![]), 1)
+        } else {
+            index += 1;
+            continue;
+        };
+
+        ops[index] = replacement;
+        removed[index + 1..index + length].fill(true);
+        index += length;
+    }
+
+    compact(ops, &removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::specialize;
+    use crate::code::code::{self, OpCode};
+    use crate::compiler::layout::Op;
+    use crate::object::object::BaseObject;
+
+    fn op(code: u8, operands: Vec<usize>) -> Op {
+        Op::new(code, operands)
+    }
+
+    #[test]
+    fn merges_sequences() {
+        let constants = [BaseObject::Integer(1), BaseObject::Integer(2)];
+        let ops = vec![
+            op(code::GetLVar::VAL, vec![5]),
+            op(code::Const::VAL, vec![0]),
+            op(code::Add::VAL, vec![]),
+            op(code::SetLVar::VAL, vec![5]),
+            op(code::GetLVar::VAL, vec![2]),
+            op(code::Const::VAL, vec![1]),
+            op(code::Add::VAL, vec![]),
+            op(code::Lt::VAL, vec![]),
+            op(code::JumpNotTrue::VAL, vec![11]),
+            op(code::GetLVar::VAL, vec![4]),
+            op(code::Return::VAL, vec![]),
+            op(code::Null::VAL, vec![]),
+            op(code::Return::VAL, vec![]),
+        ];
+        assert_eq!(
+            specialize(ops, &constants),
+            vec![
+                op(code::IncLocal::VAL, vec![5]),
+                op(code::GetLVar2::VAL, vec![]),
+                op(code::AddConst::VAL, vec![1]),
+                op(code::LtJumpNotTrue::VAL, vec![6]),
+                op(code::GetLVar::VAL, vec![4]),
+                op(code::Return::VAL, vec![]),
+                op(code::ReturnNull::VAL, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_jump_targets() {
+        // Both the `Add` and the `Return` are landed on by a jump, so nothing can be merged
+        let ops = vec![
+            op(code::JumpNotTrue::VAL, vec![3]),
+            op(code::Null::VAL, vec![]),
+            op(code::Const::VAL, vec![0]),
+            op(code::Add::VAL, vec![]),
+            op(code::Jump::VAL, vec![6]),
+            op(code::Null::VAL, vec![]),
+            op(code::Return::VAL, vec![]),
+        ];
+        assert_eq!(specialize(ops.clone(), &[BaseObject::Integer(1)]), ops);
+    }
+}
@@ -18,6 +18,12 @@ pub struct SymbolRegistry {
     registry: Vec<SymMap>,
 }
 
+impl Default for SymbolRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SymbolRegistry {
     pub fn new() -> Self {
         Self {
@@ -54,6 +60,18 @@ impl SymbolRegistry {
             })
     }
 
+    /** Looks up a name only in the current scope, which is where an assignment would store it */
+    pub fn lookup_current(&self, id: &str) -> Option<&Symbol> {
+        self.last().get(id)
+    }
+
+    /** Names registered in the current scope, ordered by their index */
+    pub fn names(&self) -> Vec<String> {
+        let mut symbols: Vec<&Symbol> = self.last().values().collect();
+        symbols.sort_by_key(|sym| sym.index);
+        symbols.into_iter().map(|sym| sym.id.clone()).collect()
+    }
+
     pub fn size(&self) -> usize {
         self.last().len()
     }
@@ -0,0 +1,299 @@
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+use crate::code::debug::lookup;
+use crate::code::words::Code;
+use crate::vm::debug::{DebugHook, DebugState};
+
+/*
+ * A command-line debugger, driven by the VM's debug hook. The program is paused before its first
+ * statement, and again whenever it reaches a breakpoint or finishes a step. While paused, commands
+ * are read one per line:
+ *
+ * break <line | function>   (b)   Pause at a source line, or whenever a named function is entered
+ * clear <line | function>         Remove a breakpoint
+ * continue                  (c)   Run until the next breakpoint
+ * step                      (s)   Run to the next statement, following calls into functions
+ * next                      (n)   Run to the next statement in this function or its callers
+ * finish                    (f)   Run until this function returns
+ * locals                          Print the params and locals of this function
+ * print <name>              (p)   Print a local, or a global if there's no local by that name
+ * backtrace                 (bt)  Print the functions being called, innermost first
+ * stack                           Print the values this function has pushed onto the stack
+ * quit                      (q)   Stop the program, which ends the run with `RuntimeError::Stopped`
+ *
+ * Stepping goes by statement when the program was compiled with line info, and by instruction
+ * otherwise.
+ */
+
+enum Mode {
+    Continue,
+    Step,
+    /** Stepping over calls made from the frame at this depth */
+    Next(usize),
+    /** Waiting for the frame at this depth to return */
+    Finish(usize),
+}
+
+pub struct Debugger<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    global_names: Vec<String>,
+    line_breaks: HashSet<usize>,
+    function_breaks: HashSet<String>,
+    mode: Mode,
+    quit: bool,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(input: R, output: W, global_names: Vec<String>) -> Self {
+        Self {
+            input,
+            output,
+            global_names,
+            line_breaks: HashSet::new(),
+            function_breaks: HashSet::new(),
+            mode: Mode::Step,
+            quit: false,
+        }
+    }
+
+    fn should_pause(&self, state: &DebugState) -> bool {
+        let depth = state.depth();
+        let code = state.code(depth);
+        let ip = state.position(depth);
+        let statement_start = code.line_starting_at(ip);
+        let at_statement = statement_start.is_some() || !code.has_lines();
+
+        let line_break = statement_start.is_some_and(|line| self.line_breaks.contains(&line));
+        let function_break =
+            ip == 0 && code.debug_info().name.as_ref().is_some_and(|name| self.function_breaks.contains(name));
+        if line_break || function_break {
+            return true;
+        }
+        match self.mode {
+            Mode::Continue => false,
+            Mode::Step => at_statement,
+            Mode::Next(from) => at_statement && depth <= from,
+            Mode::Finish(from) => depth < from,
+        }
+    }
+
+    /** Reads and runs commands until one of them resumes the program */
+    fn prompt(&mut self, state: &DebugState) {
+        self.print_location(state);
+        loop {
+            write!(self.output, "(ydb) ").unwrap();
+            self.output.flush().unwrap();
+            let mut line = String::new();
+            if self.input.read_line(&mut line).unwrap() == 0 {
+                // With no more commands to read, the program runs to the end
+                self.line_breaks.clear();
+                self.function_breaks.clear();
+                self.mode = Mode::Continue;
+                return;
+            }
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let arg = words.next();
+            match (command, arg) {
+                ("", _) => {}
+                ("break" | "b", Some(target)) => match target.parse::<usize>() {
+                    Ok(line) => {
+                        self.line_breaks.insert(line);
+                    }
+                    Err(_) => {
+                        self.function_breaks.insert(target.to_owned());
+                    }
+                },
+                ("clear", Some(target)) => {
+                    let removed = match target.parse::<usize>() {
+                        Ok(line) => self.line_breaks.remove(&line),
+                        Err(_) => self.function_breaks.remove(target),
+                    };
+                    if !removed {
+                        writeln!(self.output, "No breakpoint at {}", target).unwrap();
+                    }
+                }
+                ("continue" | "c", _) => {
+                    self.mode = Mode::Continue;
+                    return;
+                }
+                ("step" | "s", _) => {
+                    self.mode = Mode::Step;
+                    return;
+                }
+                ("next" | "n", _) => {
+                    self.mode = Mode::Next(state.depth());
+                    return;
+                }
+                ("finish" | "f", _) => {
+                    self.mode = Mode::Finish(state.depth());
+                    return;
+                }
+                ("locals", _) => {
+                    for (name, value) in state.locals(state.depth()) {
+                        writeln!(self.output, "{} = {:?}", name, value).unwrap();
+                    }
+                }
+                ("print" | "p", Some(name)) => match self.find_variable(state, name) {
+                    Some(value) => writeln!(self.output, "{} = {}", name, value).unwrap(),
+                    None => writeln!(self.output, "No variable named {}", name).unwrap(),
+                },
+                ("backtrace" | "bt", _) => {
+                    for depth in (0..=state.depth()).rev() {
+                        writeln!(self.output, "#{} {}", state.depth() - depth, describe_frame(state, depth)).unwrap();
+                    }
+                }
+                ("stack", _) => {
+                    let frame_start = state.frame(state.depth()).stack_ptr;
+                    for value in state.stack()[frame_start..].iter().rev() {
+                        writeln!(self.output, "{:?}", value).unwrap();
+                    }
+                }
+                ("quit" | "q", _) => {
+                    self.quit = true;
+                    return;
+                }
+                _ => writeln!(self.output, "Unknown command: {}", line.trim()).unwrap(),
+            }
+        }
+    }
+
+    fn find_variable(&self, state: &DebugState, name: &str) -> Option<String> {
+        let local = state.locals(state.depth()).into_iter().find(|(local, _)| *local == name);
+        let global = || {
+            let index = self.global_names.iter().position(|global| global == name)?;
+            state.globals().get(index)
+        };
+        local.map(|(_, value)| value).or_else(global).map(|value| format!("{:?}", value))
+    }
+
+    fn print_location(&mut self, state: &DebugState) {
+        let word = state.word();
+        let mnemonic = lookup(word.op).map_or("?", |(_, name)| name);
+        writeln!(self.output, "Paused in {} before {}", describe_frame(state, state.depth()), mnemonic).unwrap();
+    }
+}
+
+fn function_name(code: &Code, depth: usize) -> &str {
+    match &code.debug_info().name {
+        Some(name) => name,
+        None if depth == 0 => "<main>",
+        None => "<anonymous>",
+    }
+}
+
+/** The function a frame is running, and where it is in that function */
+fn describe_frame(state: &DebugState, depth: usize) -> String {
+    let name = function_name(state.code(depth), depth);
+    match state.line(depth) {
+        Some(line) => format!("{} at line {}", name, line),
+        None => format!("{} at instruction {}", name, state.position(depth)),
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHook for Debugger<R, W> {
+    fn before_instruction(&mut self, state: &DebugState) {
+        if self.should_pause(state) {
+            self.prompt(state);
+        }
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::compiler::compiler::Compiler;
+    use crate::object::object::Object;
+    use crate::parser::parser;
+    use crate::test_util::SharedOutput;
+    use crate::vm::error::RuntimeError;
+    use crate::vm::vm::VM;
+
+    const PROGRAM: &str = "program :debug;
+double = func(n) {
+    m = n * 2;
+    m
+};
+total = double(3);
+total = total + double(total);
+total;";
+
+    /**
+     * Runs the program with the given commands, and returns the debugger's output without prompts
+     * along with how the run ended
+     */
+    fn debug(commands: &'static str, line_info: bool) -> (String, Result<Option<Object>, RuntimeError>) {
+        let mut compiler = Compiler::new();
+        compiler.set_line_info(line_info);
+        compiler.compile_program(parser::parse_from_program(PROGRAM).unwrap());
+        let bytecode = compiler.finish();
+        let output = SharedOutput::default();
+        let debugger = Debugger::new(commands.as_bytes(), output.clone(), bytecode.global_names.clone());
+        let mut vm = VM::new(bytecode);
+        vm.set_debug_hook(Box::new(debugger));
+        let result = vm.run();
+        (output.text().replace("(ydb) ", ""), result)
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (output, _) = debug("break 3\nc\nlocals\nbt\nclear 3\nbreak double\nc\np n\np total\nc\n", true);
+        assert_eq!(
+            output,
+            "Paused in <main> at line 2 before Const
+Paused in double at line 3 before GetLVar0
+n = int(3)
+m = null
+#0 double at line 3
+#1 <main> at line 6
+Paused in double at line 3 before GetLVar0
+n = int(6)
+total = int(6)
+"
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let (output, _) = debug("n\nn\ns\ns\nf\nstack\nn\np total\n", true);
+        assert_eq!(
+            output,
+            "Paused in <main> at line 2 before Const
+Paused in <main> at line 6 before GetGVar
+Paused in <main> at line 7 before GetGVar
+Paused in double at line 3 before GetLVar0
+Paused in double at line 4 before GetLVar1
+Paused in <main> at line 7 before Add
+int(12)
+int(6)
+Paused in <main> at line 8 before GetGVar
+total = int(18)
+"
+        );
+    }
+
+    #[test]
+    fn steps_by_instruction_without_lines() {
+        let (output, _) = debug("s\ns\nc\n", false);
+        assert_eq!(
+            output,
+            "Paused in <main> at instruction 0 before Const
+Paused in <main> at instruction 1 before ToFn
+Paused in <main> at instruction 2 before SetGVar
+"
+        );
+    }
+
+    #[test]
+    fn quits() {
+        let (output, result) = debug("n\nquit\nc\n", true);
+        assert_eq!(output, "Paused in <main> at line 2 before Const\nPaused in <main> at line 6 before GetGVar\n");
+        assert_eq!(result, Err(RuntimeError::Stopped));
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Display;
+use std::path::Path;
+use std::io::{self, BufRead, Write};
+use std::fs;
+
+use crate::compiler::compiler::Compiler;
+use crate::object::object::{BaseObject, Object};
+use crate::parser::ast::Program;
+use crate::parser::parser::{parse_from_program, parse_from_statements};
+use crate::stdlib;
+use crate::vm::error::RuntimeError;
+use crate::vm::limits::VmLimits;
+use crate::vm::native::NativeFunction;
+use crate::vm::sandbox::FileAccess;
+use crate::vm::vm::VM;
+
+use super::value::Value;
+
+/**
+ * Runs YSETL code on behalf of a Rust host. Each piece of code that's run can use the globals and
+ * functions left behind by the ones before it.
+ *
+ * Parse errors, unknown globals, exceeded limits and errors from native functions come back as an
+ * `Error`. Errors that the compiler and VM don't report yet, like an undefined variable or adding a
+ * set to a number, still panic.
+ */
+pub struct Interpreter {
+    compiler: Compiler,
+    vm: VM,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(String),
+    Io(io::Error),
+    Runtime(RuntimeError),
+    UndefinedGlobal(String),
+    NotAFunction(String),
+    ArgCount { function: String, given: usize },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "Parse error: {}", err),
+            Error::Io(err) => write!(f, "Could not read program: {}", err),
+            Error::Runtime(err) => err.fmt(f),
+            Error::UndefinedGlobal(name) => write!(f, "'{}' is undefined", name),
+            Error::NotAFunction(name) => write!(f, "'{}' is not a function", name),
+            Error::ArgCount { function, given } => write!(f, "'{}' can't be called with {} args", function, given),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Error::Runtime(err)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_limits(VmLimits::default())
+    }
+
+    /** An interpreter with the standard library registered, and the given limits on its VM */
+    pub fn with_limits(limits: VmLimits) -> Self {
+        let mut compiler = Compiler::new();
+        for native in stdlib::natives() {
+            compiler.register_native(native);
+        }
+        let vm = VM::with_limits(compiler.finish_chunk(), limits);
+        Interpreter { compiler, vm }
+    }
+
+    /** Replaces stdout as where the output of `print` and the like goes */
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.vm.set_output(output);
+    }
+
+    /** Replaces stdin as where `read_line` and the like read from */
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.vm.set_input(input);
+    }
+
+    /** Sets which directories `read_file` and the other file builtins may use, which is none by default */
+    pub fn set_file_access(&mut self, access: FileAccess) {
+        self.vm.set_file_access(access);
+    }
+
+    /**
+     * Runs statements separated by semicolons, without the `program` header, and returns the value
+     * of the last one
+     */
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let expressions = parse_from_statements(source).map_err(Error::Parse)?;
+        self.run_program(Program { name: "eval", expressions })
+    }
+
+    /** Runs a program file, returning the value of its last statement */
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<Value, Error> {
+        let source = fs::read_to_string(path).map_err(Error::Io)?;
+        let program = parse_from_program(&source).map_err(Error::Parse)?;
+        self.run_program(program)
+    }
+
+    fn run_program(&mut self, program: Program) -> Result<Value, Error> {
+        self.compiler.compile_program(program);
+        self.vm.load(self.compiler.finish_chunk());
+        let last_pop = self.vm.run()?;
+        Ok(last_pop.map_or(Value::Null, |obj| Value::from_object(&obj)))
+    }
+
+    /** The value of a global variable, or `None` if no code has used it and it was never set */
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        let index = self.compiler.find_global(name)?;
+        Some(self.vm.global(index).map_or(Value::Null, Value::from_object))
+    }
+
+    /** Sets a global variable, which code that's run later can then use by name */
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.set_global_object(name, value.into().into_object());
+    }
+
+    fn set_global_object(&mut self, name: &str, value: Object) {
+        let index = self.compiler.global_index(name);
+        self.vm.set_global(index, value);
+    }
+
+    /** Binds a native function to a global of the same name, so code that's run later can call it */
+    pub fn register_native(&mut self, native: NativeFunction) {
+        let name = native.name.clone();
+        self.set_global_object(&name, BaseObject::NativeFunction(native).wrap());
+    }
+
+    /** Calls the function stored in a global variable, returning its result */
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        let index = self.compiler.find_global(name).ok_or_else(|| Error::UndefinedGlobal(name.to_owned()))?;
+        let func = self.vm.global(index).ok_or_else(|| Error::UndefinedGlobal(name.to_owned()))?.reference();
+        let accepts = match func.heap() {
+            Some(BaseObject::Function { req_params, opt_params, .. }) => {
+                (*req_params as usize..=(*req_params + *opt_params) as usize).contains(&args.len())
+            }
+            Some(BaseObject::NativeFunction(native)) => native.accepts(args.len()),
+            _ => return Err(Error::NotAFunction(name.to_owned())),
+        };
+        if !accepts {
+            return Err(Error::ArgCount { function: name.to_owned(), given: args.len() });
+        }
+        let args: Vec<Object> = args.iter().cloned().map(Value::into_object).collect();
+        let result = self.vm.call(&func, &args)?;
+        Ok(Value::from_object(&result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Interpreter};
+    use crate::interpreter::value::Value;
+    use crate::object::object::Object;
+    use crate::vm::error::RuntimeError;
+    use crate::vm::limits::{Limit, LimitExceeded, VmLimits};
+    use crate::vm::native::NativeFunction;
+
+    #[test]
+    fn keeps_globals_between_evals() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.eval("x = 2; y = [x, 3.5]").unwrap(), Value::Tuple(vec![2.into(), 3.5.into()]));
+        assert_eq!(interpreter.eval("x * 10;").unwrap(), Value::Integer(20));
+        assert_eq!(interpreter.get_global("y"), Some(Value::Tuple(vec![2.into(), 3.5.into()])));
+        assert_eq!(interpreter.get_global("z"), None);
+
+        interpreter.set_global("z", "set by host");
+        interpreter.set_global("x", 5);
+        assert_eq!(interpreter.eval("[z, x]").unwrap(), Value::Tuple(vec!["set by host".into(), 5.into()]));
+        assert_eq!(interpreter.eval("{}").unwrap(), Value::Set(vec![]));
+    }
+
+    #[test]
+    fn calls_functions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("add = func(a, b, c?) { if c == null ? a + b : a + b + c }; offset = 100").unwrap();
+        assert_eq!(interpreter.call("add", &[1.into(), 2.into()]).unwrap(), Value::Integer(3));
+        assert_eq!(interpreter.call("add", &[1.into(), 2.into(), 3.into()]).unwrap(), Value::Integer(6));
+
+        // Functions can be passed back into the program like any other value
+        let Some(add) = interpreter.get_global("add") else { panic!("add is missing") };
+        interpreter.set_global("plus", add);
+        assert_eq!(interpreter.eval("plus(offset, 1)").unwrap(), Value::Integer(101));
+
+        interpreter.register_native(NativeFunction::new("negate", 1, Some(1), |_, args| match args[0] {
+            Object::Integer(val) => Ok(Object::Integer(-val)),
+            _ => Err(RuntimeError::native("negate needs an integer")),
+        }));
+        assert_eq!(interpreter.eval("negate(add(1, 2))").unwrap(), Value::Integer(-3));
+        assert_eq!(interpreter.call("negate", &[4.into()]).unwrap(), Value::Integer(-4));
+        assert!(matches!(interpreter.call("negate", &["a".into()]), Err(Error::Runtime(RuntimeError::Native(_)))));
+        assert!(matches!(interpreter.call("negate", &[]), Err(Error::ArgCount { given: 0, .. })));
+
+        assert!(matches!(interpreter.call("add", &[1.into()]), Err(Error::ArgCount { given: 1, .. })));
+        assert!(matches!(interpreter.call("offset", &[]), Err(Error::NotAFunction(_))));
+        assert!(matches!(interpreter.call("missing", &[]), Err(Error::UndefinedGlobal(_))));
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut interpreter = Interpreter::with_limits(VmLimits { max_frames: Some(10), ..VmLimits::default() });
+        assert!(matches!(interpreter.eval("x = ;"), Err(Error::Parse(_))));
+        assert!(matches!(interpreter.run_file("no/such/file.ysetl"), Err(Error::Io(_))));
+
+        interpreter.eval("down = 0; down = func(n) { if n == 0 ? 0 : 1 + down(n - 1) }").unwrap();
+        assert!(matches!(
+            interpreter.eval("down(20)"),
+            Err(Error::Runtime(RuntimeError::Limit(LimitExceeded { limit: Limit::Frames, max: 10 })))
+        ));
+        // The interpreter can carry on after an error
+        assert_eq!(interpreter.eval("down(5)").unwrap(), Value::Integer(5));
+        assert_eq!(interpreter.call("down", &[3.into()]).unwrap(), Value::Integer(3));
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt::Debug;
+
+use crate::object::object::{BaseObject, Object};
+
+/**
+ * A YSETL value as the host sees it, copied out of the VM so it can be kept around after the
+ * program moves on. Functions are the exception, and stay a handle to the VM's function.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Tuple(Vec<Value>),
+    /** The elements of a set, in no particular order */
+    Set(Vec<Value>),
+    Function(Function),
+}
+
+/** A YSETL function, which can be called through the interpreter or stored in a global */
+#[derive(Clone, PartialEq)]
+pub struct Function(Object);
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Function")
+    }
+}
+
+impl Value {
+    pub(crate) fn from_object(obj: &Object) -> Value {
+        match obj {
+            Object::Null => Value::Null,
+            Object::True => Value::Bool(true),
+            Object::False => Value::Bool(false),
+            Object::Integer(val) => Value::Integer(*val),
+            Object::Float(val) => Value::Float(*val),
+            Object::Heap(inner) => match inner.as_ref() {
+                BaseObject::String(str) => Value::String(str.to_string()),
+                BaseObject::Tuple(els) => Value::Tuple(els.iter().map(Value::from_object).collect()),
+                BaseObject::Set(els) => Value::Set(els.iter().map(Value::from_object).collect()),
+                BaseObject::Function { .. } | BaseObject::NativeFunction(_) => Value::Function(Function(obj.reference())),
+                // Scalars are never boxed
+                BaseObject::Null | BaseObject::True | BaseObject::False | BaseObject::Integer(_) | BaseObject::Float(_) => {
+                    unreachable!("Scalar {:?} was boxed", inner)
+                }
+            },
+        }
+    }
+
+    pub(crate) fn into_object(self) -> Object {
+        match self {
+            Value::Null => Object::Null,
+            Value::Bool(val) => Object::from_bool(val),
+            Value::Integer(val) => Object::Integer(val),
+            Value::Float(val) => Object::Float(val),
+            Value::String(str) => BaseObject::String(str.into()).wrap(),
+            Value::Tuple(els) => BaseObject::Tuple(els.into_iter().map(Value::into_object).collect()).wrap(),
+            Value::Set(els) => BaseObject::Set(els.into_iter().map(Value::into_object).collect()).wrap(),
+            Value::Function(Function(obj)) => obj,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Value::Bool(val)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(val: i64) -> Self {
+        Value::Integer(val)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::Float(val)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Value::String(val.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Value::String(val)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(els: Vec<Value>) -> Self {
+        Value::Tuple(els)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+
+    #[test]
+    fn round_trips() {
+        let values = [
+            Value::Null,
+            Value::Bool(true),
+            Value::Integer(-3),
+            Value::Float(0.5),
+            Value::String("abc".to_owned()),
+            Value::Tuple(vec![Value::Integer(1), Value::Tuple(vec![])]),
+            Value::Set(vec![Value::String("x".to_owned())]),
+        ];
+        for value in values {
+            assert_eq!(Value::from_object(&value.clone().into_object()), value);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+/*!
+ * YSETL as a library. The `Interpreter` is the entry point for running YSETL code from Rust: it
+ * keeps the globals from one piece of code to the next, and hands values back and forth as
+ * `Value`s. Rust functions can be made callable from YSETL by registering them as `NativeFunction`s.
+ *
+ * ```
+ * let mut interpreter = ysetl::Interpreter::new();
+ * interpreter.eval("double = func(n) { n * 2 };").unwrap();
+ * assert_eq!(interpreter.call("double", &[21.into()]).unwrap(), ysetl::Value::Integer(42));
+ * ```
+ *
+ * The modules beneath it are the compiler and VM that it's built on, for tools that need to work
+ * with the bytecode directly.
+ */
+
+pub mod bench;
+pub mod code;
+pub mod compiler;
+pub mod debugger;
+pub mod interpreter;
+pub mod object;
+pub mod parser;
+pub mod profiler;
+pub mod stdlib;
+pub mod tracer;
+pub mod vm;
+
+#[cfg(test)]
+mod test_util;
+
+pub use interpreter::interpreter::{Error, Interpreter};
+pub use interpreter::value::{Function, Value};
+pub use vm::error::RuntimeError;
+pub use vm::native::{NativeFunction, VmCtx};
@@ -1,25 +1,160 @@
-use std::fs;
-use compiler::compiler::Compiler;
-use parser::parser::parse_from_program;
-use vm::vm::VM;
+use std::{env, fs, io};
+use ysetl::bench;
+use ysetl::code::disassembler::disassemble;
+use ysetl::compiler::compiler::Compiler;
+use ysetl::debugger::Debugger;
+use ysetl::object::display::pretty;
+use ysetl::parser::parser::parse_from_program;
+use ysetl::profiler::Profiler;
+use ysetl::stdlib;
+use ysetl::tracer::{TraceFilter, Tracer};
+use ysetl::vm::error::RuntimeError;
+use ysetl::vm::limits::VmLimits;
+use ysetl::vm::sandbox::FileAccess;
+use ysetl::vm::vm::VM;
 
-use crate::code::debug::print_bytes;
+static INPUT_PATH: &str = "program.ysetl";
 
-pub mod code;
-pub mod compiler;   
-pub mod object;
-pub mod parser;
-pub mod vm;
+struct Args {
+    path: String,
+    optimize: bool,
+    bench: bool,
+    debug: bool,
+    trace: Option<TraceFilter>,
+    /** Set when profiling, along with where to write the folded stacks if anywhere */
+    profile: Option<Option<String>>,
+    limits: VmLimits,
+    file_access: FileAccess,
+}
+
+/**
+ * Usage: ysetl [-O] [--bench] [--debug] [--trace] [--profile[=FILE]] [--max-<limit>=N ...]
+ *              [--allow-read=DIR ...] [--allow-write=DIR ...] [path], where:
+ * - -O enables the optimization passes
+ * - --bench runs the VM benchmarks instead of a program
+ * - --debug runs the program under the debugger
+ * - --trace logs each instruction to stderr, limited to one function with --trace-fn=NAME or to
+ *   a range of source lines with --trace-lines=FIRST-LAST (either one implies --trace)
+ * - --profile prints how long each function ran for and how often each opcode ran once the program
+ *   is over, and --profile=FILE also writes the time spent in each stack of calls to FILE, in the
+ *   folded format that flamegraph tools read
+ * - the max flags set the VM's limits: --max-instructions, --max-stack, --max-frames and
+ *   --max-collection
+ * - --allow-read and --allow-write let the program's file builtins read or write anything beneath a
+ *   directory, and can be given more than once. Without them, the program can't use files at all.
+ */
+fn parse_args() -> Args {
+    let mut args = Args {
+        path: INPUT_PATH.to_owned(),
+        optimize: false,
+        bench: false,
+        debug: false,
+        trace: None,
+        profile: None,
+        limits: VmLimits::default(),
+        file_access: FileAccess::default(),
+    };
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-O" => args.optimize = true,
+            "--bench" => args.bench = true,
+            "--debug" => args.debug = true,
+            "--profile" => args.profile = Some(None),
+            flag if flag.starts_with("--profile=") => args.profile = Some(Some(flag["--profile=".len()..].to_owned())),
+            "--trace" => {
+                args.trace.get_or_insert_with(TraceFilter::default);
+            }
+            flag if flag.starts_with("--trace-") => set_trace_filter(args.trace.get_or_insert_with(TraceFilter::default), flag),
+            flag if flag.starts_with("--max-") => set_limit(&mut args.limits, flag),
+            flag if flag.starts_with("--allow-") => allow_access(&mut args.file_access, flag),
+            flag if flag.starts_with('-') => panic!("Unknown flag {}", flag),
+            path => args.path = path.to_owned(),
+        }
+    }
+    args
+}
+
+fn set_limit(limits: &mut VmLimits, flag: &str) {
+    let (name, value) = flag.split_once('=').unwrap_or_else(|| panic!("Expected a value for {}", flag));
+    let value: usize = value.parse().unwrap_or_else(|_| panic!("Invalid value for {}: {}", name, value));
+    match name {
+        "--max-instructions" => limits.max_instructions = Some(value as u64),
+        "--max-stack" => limits.max_stack = Some(value),
+        "--max-frames" => limits.max_frames = Some(value),
+        "--max-collection" => limits.max_collection_size = Some(value),
+        _ => panic!("Unknown flag {}", name),
+    }
+}
+
+fn allow_access(access: &mut FileAccess, flag: &str) {
+    let (name, dir) = flag.split_once('=').unwrap_or_else(|| panic!("Expected a directory for {}", flag));
+    let allowed = match name {
+        "--allow-read" => access.allow_read(dir),
+        "--allow-write" => access.allow_write(dir),
+        _ => panic!("Unknown flag {}", name),
+    };
+    allowed.unwrap_or_else(|err| panic!("Can't allow access to {}: {}", dir, err));
+}
 
-static INPUT_PATH: &'static str = "program.ysetl";
+fn set_trace_filter(filter: &mut TraceFilter, flag: &str) {
+    let (name, value) = flag.split_once('=').unwrap_or_else(|| panic!("Expected a value for {}", flag));
+    let line = |value: &str| -> usize {
+        value.parse().unwrap_or_else(|_| panic!("Invalid line for {}: {}", name, value))
+    };
+    match name {
+        "--trace-fn" => filter.function = Some(value.to_owned()),
+        "--trace-lines" => {
+            let (first, last) = value.split_once('-').unwrap_or((value, value));
+            filter.lines = Some(line(first)..=line(last));
+        }
+        _ => panic!("Unknown flag {}", name),
+    }
+}
 
 fn main() {
-    let input = fs::read_to_string(INPUT_PATH).unwrap();
+    let args = parse_args();
+    if args.bench {
+        bench::run_benchmarks(args.optimize);
+        return;
+    }
+    let input = fs::read_to_string(&args.path).unwrap();
     let expr = parse_from_program(&input).unwrap();
-    let mut compiler = Compiler::new();
+    let mut compiler = Compiler::with_optimizations(args.optimize);
+    compiler.set_line_info(args.debug || args.trace.is_some() || args.profile.is_some());
+    for native in stdlib::natives() {
+        compiler.register_native(native);
+    }
     compiler.compile_program(expr);
     let bc = compiler.finish();
-    println!("{}", print_bytes(&bc.instuctions));
-    let mut vm = VM::new(bc);
-    println!("Last pop: {:?}", vm.run());
+    println!("{}", disassemble(&bc));
+    let global_names = bc.global_names.clone();
+    let mut vm = VM::with_limits(bc, args.limits);
+    vm.set_file_access(args.file_access);
+    let mut profile = None;
+    match (args.debug, args.trace, &args.profile) {
+        (true, None, None) => vm.set_debug_hook(Box::new(Debugger::new(io::stdin().lock(), io::stdout(), global_names))),
+        (false, Some(filter), None) => vm.set_debug_hook(Box::new(Tracer::new(io::stderr(), filter))),
+        (false, None, Some(_)) => {
+            let profiler = Profiler::new();
+            profile = Some(profiler.profile());
+            vm.set_debug_hook(Box::new(profiler));
+        }
+        (false, None, None) => {}
+        _ => panic!("Only one of --debug, --trace and --profile can be used at a time"),
+    }
+    match vm.run() {
+        Ok(Some(last_pop)) => println!("Last pop: {}", pretty(&last_pop, 80)),
+        Ok(None) => println!("Last pop: nothing"),
+        // The debugger was told to quit, so there's nothing more to say
+        Err(RuntimeError::Stopped) => {}
+        Err(err) => eprintln!("{}", err),
+    }
+    if let Some(profile) = profile {
+        let mut profile = profile.borrow_mut();
+        profile.finish();
+        eprint!("{}", profile.table());
+        if let Some(Some(path)) = &args.profile {
+            fs::write(path, profile.folded()).unwrap();
+        }
+    }
 }
@@ -0,0 +1,178 @@
+use std::rc::Rc;
+
+use super::object::{BaseObject, Object, ObjectOps};
+use crate::code::code::{self, OpCode};
+
+/*
+ * Operations that update a tuple or set. A collection that nothing else references is changed in
+ * place. A shared one is copied first, but since tuples and sets are persistent, that copy shares
+ * its structure with the original, and the original stays valid for anything still holding it.
+ */
+
+/** Applies `with`, `less` or `union` to `target`, returning false if the types don't support it */
+pub fn update(target: &mut Object, value: &Object, op: u8) -> bool {
+    let Object::Heap(inner) = target else {
+        return false;
+    };
+    let supported = match (inner.as_ref(), op) {
+        (BaseObject::Tuple(_), code::With::VAL) => true,
+        (BaseObject::Set(_), code::With::VAL | code::Less::VAL) => true,
+        (BaseObject::Set(_), code::Union::VAL) => matches!(value.heap(), Some(BaseObject::Set(_))),
+        _ => false,
+    };
+    if !supported {
+        return false;
+    }
+
+    match (Rc::make_mut(inner), op) {
+        (BaseObject::Tuple(els), code::With::VAL) => els.push_back(value.reference()),
+        (BaseObject::Set(els), code::With::VAL) => {
+            els.insert(value.reference());
+        }
+        (BaseObject::Set(els), code::Less::VAL) => {
+            els.remove(value);
+        }
+        (BaseObject::Set(els), code::Union::VAL) => {
+            if let Some(BaseObject::Set(other)) = value.heap() {
+                els.extend(other.iter().cloned());
+            }
+        }
+        _ => unreachable!(),
+    }
+    true
+}
+
+/**
+ * Replaces the element of `target` at `index`. Assigning one past the end of a tuple appends to
+ * it, and assigning further out fills the gap with null.
+ */
+pub fn set_index(target: &mut Object, index: &Object, value: Object) {
+    let i = match (target.heap(), index) {
+        (Some(BaseObject::Tuple(_)), &Object::Integer(i)) if i >= 0 => i as usize,
+        (Some(BaseObject::Tuple(_)), &Object::Integer(i)) => panic!("{} is out of index for tuple", i),
+        (Some(BaseObject::Tuple(_)), _) => panic!("Cannot index into tuple with {:?}", index),
+        _ => panic!("Cannot assign to an index of {:?}", target),
+    };
+    let Object::Heap(inner) = target else { unreachable!() };
+    let BaseObject::Tuple(els) = Rc::make_mut(inner) else { unreachable!() };
+    if i < els.len() {
+        els.set(i, value);
+    } else {
+        els.extend((els.len()..i).map(|_| Object::Null));
+        els.push_back(value);
+    }
+}
+
+/**
+ * Replaces an element nested inside `target`, following `path` one index at a time, so
+ * `set_path(t, [i, j], x)` does what `t[i][j] = x` says. Each collection along the way is updated
+ * in place if nothing else refers to it.
+ */
+pub fn set_path(target: &mut Object, path: &[Object], value: Object) {
+    match path {
+        [] => *target = value,
+        [index] => set_index(target, index, value),
+        [index, rest @ ..] => {
+            // The element is taken out while it's updated, so it isn't shared with `target`
+            let mut inner = target.get_index(index);
+            set_index(target, index, Object::Null);
+            set_path(&mut inner, rest, value);
+            set_index(target, index, inner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_index, set_path, update};
+    use crate::code::code::{self, OpCode};
+    use crate::object::object::{BaseObject, Object};
+
+    fn tuple(values: &[i64]) -> Object {
+        BaseObject::Tuple(values.iter().map(|v| Object::Integer(*v)).collect()).wrap()
+    }
+
+    fn set(values: &[i64]) -> Object {
+        BaseObject::Set(values.iter().map(|v| Object::Integer(*v)).collect()).wrap()
+    }
+
+    fn apply(target: &Object, value: &Object, op: u8) -> Option<Object> {
+        let mut result = target.reference();
+        update(&mut result, value, op).then_some(result)
+    }
+
+    #[test]
+    fn with_and_less() {
+        let original = set(&[1, 2]);
+        let added = apply(&original, &Object::Integer(3), code::With::VAL).unwrap();
+        assert_eq!(added, set(&[3, 2, 1]));
+        // The original version is untouched since it was shared
+        assert_eq!(original, set(&[1, 2]));
+
+        let removed = apply(&added, &Object::Integer(1), code::Less::VAL).unwrap();
+        assert_eq!(removed, set(&[2, 3]));
+
+        let appended = apply(&tuple(&[1]), &Object::Integer(1), code::With::VAL).unwrap();
+        assert_eq!(appended, tuple(&[1, 1]));
+
+        assert_eq!(apply(&tuple(&[1]), &Object::Integer(1), code::Less::VAL), None);
+        assert_eq!(apply(&Object::Integer(1), &Object::Integer(1), code::With::VAL), None);
+    }
+
+    #[test]
+    fn updates_in_place_when_unique() {
+        let mut target = set(&[1]);
+        let before = target.heap().unwrap() as *const BaseObject;
+        assert!(update(&mut target, &Object::Integer(2), code::With::VAL));
+        assert_eq!(target.heap().unwrap() as *const BaseObject, before);
+
+        let shared = target.reference();
+        assert!(update(&mut target, &Object::Integer(3), code::With::VAL));
+        assert_ne!(target.heap().unwrap() as *const BaseObject, before);
+        assert_eq!(shared, set(&[1, 2]));
+    }
+
+    #[test]
+    fn union() {
+        let result = apply(&set(&[1, 2]), &set(&[2, 3]), code::Union::VAL).unwrap();
+        assert_eq!(result, set(&[1, 2, 3]));
+        assert_eq!(apply(&set(&[1]), &tuple(&[2]), code::Union::VAL), None);
+    }
+
+    #[test]
+    fn index_assignment() {
+        let original = tuple(&[1, 2, 3]);
+        let mut updated = original.reference();
+        set_index(&mut updated, &Object::Integer(1), Object::Integer(9));
+        assert_eq!(updated, tuple(&[1, 9, 3]));
+        assert_eq!(original, tuple(&[1, 2, 3]));
+
+        set_index(&mut updated, &Object::Integer(4), Object::Integer(5));
+        assert_eq!(
+            updated,
+            BaseObject::Tuple(
+                [Object::Integer(1), Object::Integer(9), Object::Integer(3), Object::Null, Object::Integer(5)]
+                    .into_iter()
+                    .collect()
+            )
+            .wrap()
+        );
+    }
+
+    #[test]
+    fn nested_index_assignment() {
+        let original = BaseObject::Tuple([tuple(&[1, 2]), tuple(&[3])].into_iter().collect()).wrap();
+        let mut updated = original.reference();
+        set_path(&mut updated, &[Object::Integer(0), Object::Integer(1)], Object::Integer(9));
+        assert_eq!(updated, BaseObject::Tuple([tuple(&[1, 9]), tuple(&[3])].into_iter().collect()).wrap());
+        assert_eq!(original, BaseObject::Tuple([tuple(&[1, 2]), tuple(&[3])].into_iter().collect()).wrap());
+    }
+
+    #[test]
+    fn nested_sets_are_values() {
+        // Sets of sets compare by contents, regardless of insertion order
+        let a = BaseObject::Set([set(&[1, 2]), set(&[3])].into_iter().collect()).wrap();
+        let b = BaseObject::Set([set(&[3]), set(&[2, 1])].into_iter().collect()).wrap();
+        assert_eq!(a, b);
+    }
+}
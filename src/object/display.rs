@@ -0,0 +1,267 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Write};
+
+use im_rc::HashSet;
+
+use super::object::{BaseObject, Object};
+
+/*
+ * Values are written the way they would be in source code, so `[1, "a\n", {2, 3}]` prints as
+ * exactly that. Sets are written in sorted order so that equal sets always print the same way, and
+ * `pretty` breaks values that don't fit in a line across several.
+ */
+
+/** Where a value's kind comes in the order used to sort the elements of a set */
+fn kind_rank(obj: &Object) -> u8 {
+    match obj {
+        Object::Null => 0,
+        Object::False | Object::True => 1,
+        Object::Integer(_) | Object::Float(_) => 2,
+        Object::Heap(inner) => match inner.as_ref() {
+            BaseObject::String(_) => 3,
+            BaseObject::Tuple(_) => 4,
+            BaseObject::Set(_) => 5,
+            BaseObject::Function { .. } | BaseObject::NativeFunction(_) => 6,
+            // Scalars are never boxed
+            _ => unreachable!("Scalar {:?} was boxed", inner),
+        },
+    }
+}
+
+/**
+ * A total order over values, used to print sets deterministically. Values are ordered by kind
+ * (null, booleans, numbers, strings, tuples, sets and then functions), then by value. Integers and
+ * floats are compared numerically, with an integer coming before the float equal to it.
+ */
+pub fn compare(a: &Object, b: &Object) -> Ordering {
+    kind_rank(a).cmp(&kind_rank(b)).then_with(|| match (a, b) {
+        (Object::False, Object::True) => Ordering::Less,
+        (Object::True, Object::False) => Ordering::Greater,
+        (Object::Integer(a), Object::Integer(b)) => a.cmp(b),
+        (Object::Float(a), Object::Float(b)) => a.total_cmp(b),
+        (Object::Integer(a), Object::Float(b)) => (*a as f64).total_cmp(b).then(Ordering::Less),
+        (Object::Float(a), Object::Integer(b)) => a.total_cmp(&(*b as f64)).then(Ordering::Greater),
+        (Object::Heap(a), Object::Heap(b)) => match (a.as_ref(), b.as_ref()) {
+            (BaseObject::String(a), BaseObject::String(b)) => a.cmp(b),
+            (BaseObject::Tuple(a), BaseObject::Tuple(b)) => compare_elements(a.iter(), b.iter()),
+            (BaseObject::Set(a), BaseObject::Set(b)) => compare_elements(sorted(a).into_iter(), sorted(b).into_iter()),
+            // Functions have no natural order, so they're ordered by how they print
+            (a, b) => a.to_string().cmp(&b.to_string()),
+        },
+        _ => Ordering::Equal,
+    })
+}
+
+fn compare_elements<'a>(mut a: impl Iterator<Item = &'a Object>, mut b: impl Iterator<Item = &'a Object>) -> Ordering {
+    loop {
+        match (a.next(), b.next()) {
+            (Some(a), Some(b)) => match compare(a, b) {
+                Ordering::Equal => continue,
+                order => return order,
+            },
+            (a, b) => return a.is_some().cmp(&b.is_some()),
+        }
+    }
+}
+
+/** The elements of a set in the order they're printed in */
+pub fn sorted(els: &HashSet<Object>) -> Vec<&Object> {
+    let mut els: Vec<&Object> = els.iter().collect();
+    els.sort_by(|a, b| compare(a, b));
+    els
+}
+
+/** Writes a string as a literal, escaping the characters that can't be written as they are */
+fn write_string(f: &mut impl Write, str: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for ch in str.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            ch if ch.is_control() => write!(f, "\\u{{{:x}}}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/** Writes a function's params as they were declared, such as `func(a, b?, c!)` */
+fn write_function(f: &mut impl Write, base: &BaseObject) -> std::fmt::Result {
+    match base {
+        BaseObject::Function { ins, req_params, opt_params, locked_values, .. } => {
+            let (req, opt) = (*req_params as usize, *opt_params as usize);
+            let names = &ins.debug_info().locals;
+            f.write_str("func(")?;
+            for i in 0..req + opt + locked_values.len() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                // Assembled functions don't know their params' names
+                f.write_str(names.get(i).map_or("_", String::as_str))?;
+                if i >= req + opt {
+                    f.write_char('!')?;
+                } else if i >= req {
+                    f.write_char('?')?;
+                }
+            }
+            f.write_char(')')
+        }
+        // Native functions have no source of their own, so they're written as the name they're bound to
+        BaseObject::NativeFunction(native) => f.write_str(&native.name),
+        _ => unreachable!("{:?} is not a function", base),
+    }
+}
+
+/** Writes each element with `Display`, separated by commas */
+fn write_elements<'a>(f: &mut impl Write, els: impl Iterator<Item = &'a Object>) -> std::fmt::Result {
+    for (i, el) in els.enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", el)?;
+    }
+    Ok(())
+}
+
+impl Display for BaseObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::True => f.write_str("true"),
+            Self::False => f.write_str("false"),
+            Self::Integer(val) => write!(f, "{}", val),
+            // Debug keeps the decimal point on whole numbers, so they still read as floats
+            Self::Float(val) => write!(f, "{:?}", val),
+            Self::String(str) => write_string(f, str),
+            Self::Tuple(els) => {
+                f.write_str("[")?;
+                write_elements(f, els.iter())?;
+                f.write_str("]")
+            }
+            Self::Set(els) => {
+                f.write_str("{")?;
+                write_elements(f, sorted(els).into_iter())?;
+                f.write_str("}")
+            }
+            Self::Function { .. } | Self::NativeFunction(_) => write_function(f, self),
+        }
+    }
+}
+
+impl Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::True => f.write_str("true"),
+            Self::False => f.write_str("false"),
+            Self::Integer(val) => write!(f, "{}", val),
+            Self::Float(val) => write!(f, "{:?}", val),
+            Self::Heap(inner) => Display::fmt(inner.as_ref(), f),
+        }
+    }
+}
+
+/**
+ * Adds a value to a string as `print` and `str` write it: strings as they are, and anything
+ * else as `Display` does
+ */
+pub fn push_text(output: &mut String, value: &Object) {
+    match value.heap() {
+        Some(BaseObject::String(str)) => output.push_str(str),
+        _ => write!(output, "{}", value).unwrap(),
+    }
+}
+
+/**
+ * Writes a value as `Display` does when it fits in `width` columns. Otherwise, a tuple or set is
+ * written with one element per line, indented by two spaces, and the same goes for each element.
+ */
+pub fn pretty(value: &Object, width: usize) -> String {
+    let mut output = String::new();
+    write_pretty(&mut output, value, 0, width);
+    output
+}
+
+fn write_pretty(output: &mut String, value: &Object, indent: usize, width: usize) {
+    let flat = value.to_string();
+    let (open, close, els) = match value.heap() {
+        Some(BaseObject::Tuple(els)) if !els.is_empty() => ('[', ']', els.iter().collect()),
+        Some(BaseObject::Set(els)) if !els.is_empty() => ('{', '}', sorted(els)),
+        _ => ('\0', '\0', vec![]),
+    };
+    if els.is_empty() || indent + flat.chars().count() <= width {
+        output.push_str(&flat);
+        return;
+    }
+    output.push(open);
+    for (i, el) in els.iter().enumerate() {
+        output.push('\n');
+        output.push_str(&" ".repeat(indent + 2));
+        write_pretty(output, el, indent + 2, width);
+        if i + 1 < els.len() {
+            output.push(',');
+        }
+    }
+    output.push('\n');
+    output.push_str(&" ".repeat(indent));
+    output.push(close);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pretty;
+    use crate::object::object::{BaseObject, Object};
+
+    fn string(str: &str) -> Object {
+        BaseObject::String(str.into()).wrap()
+    }
+
+    fn tuple(els: Vec<Object>) -> Object {
+        BaseObject::Tuple(els.into_iter().collect()).wrap()
+    }
+
+    fn set(els: Vec<Object>) -> Object {
+        BaseObject::Set(els.into_iter().collect()).wrap()
+    }
+
+    #[test]
+    fn writes_source_syntax() {
+        assert_eq!(Object::Null.to_string(), "null");
+        assert_eq!(Object::Float(2.0).to_string(), "2.0");
+        assert_eq!(string("say \"hi\"\n\tand\\or\u{1}").to_string(), r#""say \"hi\"\n\tand\\or\u{1}""#);
+        assert_eq!(tuple(vec![Object::Integer(1), string("a"), tuple(vec![])]).to_string(), r#"[1, "a", []]"#);
+    }
+
+    #[test]
+    fn sorts_sets() {
+        let value = set(vec![
+            string("b"),
+            Object::Float(1.5),
+            tuple(vec![Object::Integer(2)]),
+            Object::Integer(1),
+            set(vec![Object::Integer(3), Object::Integer(2)]),
+            string("a"),
+            Object::True,
+            tuple(vec![Object::Integer(1), Object::Integer(5)]),
+            Object::Null,
+            Object::Float(1.0),
+            Object::False,
+        ]);
+        assert_eq!(value.to_string(), r#"{null, false, true, 1, 1.0, 1.5, "a", "b", [1, 5], [2], {2, 3}}"#);
+    }
+
+    #[test]
+    fn breaks_long_values() {
+        let value = tuple(vec![
+            Object::Integer(1),
+            set(vec![string("abc"), string("def")]),
+            tuple(vec![Object::Integer(2), Object::Integer(3)]),
+        ]);
+        assert_eq!(pretty(&value, 80), value.to_string());
+        assert_eq!(pretty(&value, 20), "[\n  1,\n  {\"abc\", \"def\"},\n  [2, 3]\n]");
+        assert_eq!(pretty(&value, 10), "[\n  1,\n  {\n    \"abc\",\n    \"def\"\n  },\n  [2, 3]\n]");
+    }
+}
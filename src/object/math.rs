@@ -1,6 +1,5 @@
-use std::rc::Rc;
-
-use super::object::BaseObject::{self, *};
+use super::object::Object;
+use super::string::string_op;
 use crate::code::code::{self, OpCode};
 
 pub trait ObjectMath {
@@ -8,75 +7,77 @@ pub trait ObjectMath {
     fn negate(&self) -> Option<Self> where Self: Sized;
 }
 
-fn int_math(left: i64, right: i64, op: u8) -> BaseObject {
+fn int_math(left: i64, right: i64, op: u8) -> Object {
     match op {
-        code::Add::VAL => Integer(left + right),
-        code::Subtract::VAL => Integer(left - right),
-        code::Mult::VAL => Integer(left * right),
-        code::Div::VAL => Float(left as f64 / right as f64),
+        code::Add::VAL => Object::Integer(left + right),
+        code::Subtract::VAL => Object::Integer(left - right),
+        code::Mult::VAL => Object::Integer(left * right),
+        code::Div::VAL => Object::Float(left as f64 / right as f64),
         code::IntDiv::VAL => {
             if right == 0 {
                 panic!("Divide by zero error")
             };
-            Integer(left / right)
+            Object::Integer(left / right)
         }
-        code::Exp::VAL => Integer(left.pow(right as u32)),
-        code::Lt::VAL => if left < right { True } else { False },
-        code::Lteq::VAL => if left <= right { True } else { False },
+        code::Exp::VAL => Object::Integer(left.pow(right as u32)),
+        code::Lt::VAL => Object::from_bool(left < right),
+        code::Lteq::VAL => Object::from_bool(left <= right),
         _ => unimplemented!(),
     }
 }
 
-fn float_math(left: f64, right: f64, op: u8) -> BaseObject {
+fn float_math(left: f64, right: f64, op: u8) -> Object {
     match op {
-        code::Add::VAL => Float(left + right),
-        code::Subtract::VAL => Float(left - right),
-        code::Mult::VAL => Float(left * right),
+        code::Add::VAL => Object::Float(left + right),
+        code::Subtract::VAL => Object::Float(left - right),
+        code::Mult::VAL => Object::Float(left * right),
         code::Div::VAL => {
             if right == 0.0 {
                 panic!("Divide by zero error");
             }
-            Float(left / right)
+            Object::Float(left / right)
         }
         code::IntDiv::VAL => {
             panic!("Operands for `div` must both be integers");
         }
-        code::Exp::VAL => Float(left.powf(right)),
-        code::Lt::VAL => if left < right { True } else { False },
-        code::Lteq::VAL => if left <= right { True } else { False },
+        code::Exp::VAL => Object::Float(left.powf(right)),
+        code::Lt::VAL => Object::from_bool(left < right),
+        code::Lteq::VAL => Object::from_bool(left <= right),
         _ => unimplemented!(),
     }
 }
 
-impl ObjectMath for BaseObject {
-    fn to_float(&self) -> Option<BaseObject> {
+impl ObjectMath for Object {
+    fn to_float(&self) -> Option<Object> {
         match self {
-            Float(val) => Some(Float(*val)),
-            Integer(val) => Some(Float(*val as f64)),
+            Object::Float(val) => Some(Object::Float(*val)),
+            Object::Integer(val) => Some(Object::Float(*val as f64)),
             _ => None,
         }
     }
 
-    fn negate(&self) -> Option<BaseObject> {
+    fn negate(&self) -> Option<Object> {
         match self {
-            Integer(value) => Some(Integer(-value)),
-            Float(value) => Some(Float(-value)),
+            Object::Integer(value) => Some(Object::Integer(-value)),
+            Object::Float(value) => Some(Object::Float(-value)),
             other => panic!("Cannot negate {:?}", other),
         }
     }
 }
 
-pub fn math_op(left: &Rc<BaseObject>, right: &Rc<BaseObject>, op: u8) -> Option<BaseObject> {
-    match (left.as_ref(), right.as_ref()) {
-        (&Integer(left), &Integer(right)) => {
-            return Some(int_math(left, right, op));
+pub fn math_op(left: &Object, right: &Object, op: u8) -> Option<Object> {
+    match (left, right) {
+        (&Object::Integer(left), &Object::Integer(right)) => {
+            Some(int_math(left, right, op))
         }
-        (&Integer(_), &Float(_)) | (&Float(_), &Integer(_)) | (&Float(_), &Float(_)) => {
-            let (Some(Float(left_val)), Some(Float(right_val))) = (left.to_float(), right.to_float()) else {
+        (&Object::Integer(_), &Object::Float(_))
+        | (&Object::Float(_), &Object::Integer(_))
+        | (&Object::Float(_), &Object::Float(_)) => {
+            let (Some(Object::Float(left_val)), Some(Object::Float(right_val))) = (left.to_float(), right.to_float()) else {
                 return None
             };
-            return Some(float_math(left_val, right_val, op));
+            Some(float_math(left_val, right_val, op))
         }
-        _ => None,
+        _ => string_op(left, right, op),
     }
 }
@@ -1,2 +1,6 @@
+pub mod collection;
+pub mod display;
 pub mod math;
-pub mod object;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod object;
+pub mod string;
\ No newline at end of file
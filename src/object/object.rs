@@ -1,6 +1,12 @@
-use std::{fmt::Debug, rc::Rc};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fmt::Debug;
+use std::rc::Rc;
 
-use bytes::Bytes;
+use super::string::Str;
+use crate::code::words::Code;
+use crate::vm::native::NativeFunction;
+use im_rc::{HashSet, Vector};
 
 pub trait ObjectOps {
     fn not(&self) -> Self;
@@ -12,30 +18,39 @@ pub trait ObjectOps {
 // This could be a little inefficient for space since some consts
 // will be bigger than others, and enums are sized to fit the largest
 // variant, but I prefer the speed gains from having the common data
-// structures on the stack.
-#[derive(PartialEq)]
+// structures on the stack. Tuples and sets are persistent, so an updated copy shares most of its
+// structure with the original.
+#[derive(Clone)]
 pub enum BaseObject {
     Null,
     True,
     False,
     Integer(i64),
     Float(f64),
-    String(String),
-    Tuple(Vec<Object>),
-    Set(Vec<Object>),
+    String(Str),
+    Tuple(Vector<Object>),
+    Set(HashSet<Object>),
     Function {
-        ins: Rc<Bytes>,
+        ins: Rc<Code>,
         locals: usize,
         req_params: u16,
         opt_params: u16,
         locked_values: Vec<Object>
     },
+    NativeFunction(NativeFunction),
 }
 
 impl BaseObject {
-    /** Wrap a BaseObject instance with an Object. */
+    /** Wrap a BaseObject instance with an Object. Scalars are stored inline rather than boxed. */
     pub fn wrap(self) -> Object {
-        Object { inner: Rc::new(self) }
+        match self {
+            BaseObject::Null => Object::Null,
+            BaseObject::True => Object::True,
+            BaseObject::False => Object::False,
+            BaseObject::Integer(val) => Object::Integer(val),
+            BaseObject::Float(val) => Object::Float(val),
+            other => Object::Heap(Rc::new(other)),
+        }
     }
 }
 
@@ -55,43 +70,38 @@ impl ObjectOps for BaseObject {
             BaseObject::Null => false,
             BaseObject::Integer(val) => *val != 0,
             BaseObject::Float(val) => *val != 0.0,  
-            BaseObject::String(str) => str.len() > 0,
-            BaseObject::Tuple(els) => els.len() > 0,
-            BaseObject::Set(els) => els.len() > 0,
-            BaseObject::Function {..} => true,
+            BaseObject::String(str) => !str.is_empty(),
+            BaseObject::Tuple(els) => !els.is_empty(),
+            BaseObject::Set(els) => !els.is_empty(),
+            BaseObject::Function {..} | BaseObject::NativeFunction(_) => true,
         }
     }
 
     fn is_int(&self) -> bool {
-        match self {
-            BaseObject::Integer(_) => true,
-            _ => false,
-        }
+        matches!(self, BaseObject::Integer(_))
     }
 
     fn get_index(&self, index: &Object) -> Object {
         match self {
             Self::String(str) => {
-                if let &&BaseObject::Integer(val) = &index.inner.as_ref() {
-                    let char = str.chars().nth(val as usize).map_or_else(|| {
-                        panic!("{} is out of index for string {}", val, str);
-                    }, |ch| {
-                        ch.to_string()
+                if let &Object::Integer(val) = index {
+                    let char = str.char_at(val as usize).unwrap_or_else(|| {
+                        panic!("{} is out of index for string {:?}", val, str);
                     });
-                    BaseObject::String(char).wrap()
+                    BaseObject::String(char.into()).wrap()
                 } else {
                     panic!("Cannot index into string with {:?}", index)
                 }
             }
             Self::Tuple(elements) => {
-                if let &&BaseObject::Integer(val) = &index.inner.as_ref() {
+                if let &Object::Integer(val) = index {
                     elements.get(val as usize).map_or_else(|| {
                         panic!("{} is out of index for vector", val);
                     }, |ch| {
                         ch.reference()
                     })
                 } else {
-                    panic!("Cannot index into string with {:?}", index)
+                    panic!("Cannot index into tuple with {:?}", index)
                 }
             }
             // Self::Set(elements) => {
@@ -102,6 +112,87 @@ impl ObjectOps for BaseObject {
     }
 }
 
+/**
+ * Floats are equal when `==` says so, except that NaN is equal to itself. That keeps equality
+ * reflexive, so a NaN can be found in a set.
+ */
+pub fn float_eq(a: f64, b: f64) -> bool {
+    a == b || (a.is_nan() && b.is_nan())
+}
+
+/** The bits a float is hashed by, the same for `0.0` and `-0.0` and for every NaN since they're equal */
+fn float_bits(val: f64) -> u64 {
+    if val == 0.0 {
+        0
+    } else if val.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        val.to_bits()
+    }
+}
+
+impl PartialEq for BaseObject {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) | (Self::True, Self::True) | (Self::False, Self::False) => true,
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => float_eq(*a, *b),
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Tuple(a), Self::Tuple(b)) => a == b,
+            (Self::Set(a), Self::Set(b)) => a == b,
+            (
+                Self::Function { ins, locals, req_params, opt_params, locked_values },
+                Self::Function {
+                    ins: other_ins,
+                    locals: other_locals,
+                    req_params: other_req_params,
+                    opt_params: other_opt_params,
+                    locked_values: other_locked_values,
+                },
+            ) => {
+                ins == other_ins
+                    && locals == other_locals
+                    && req_params == other_req_params
+                    && opt_params == other_opt_params
+                    && locked_values == other_locked_values
+            }
+            (Self::NativeFunction(a), Self::NativeFunction(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Hash for BaseObject {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Null | Self::True | Self::False => {}
+            Self::Integer(val) => val.hash(state),
+            Self::Float(val) => float_bits(*val).hash(state),
+            Self::String(str) => str.hash(state),
+            Self::Tuple(els) => els.hash(state),
+            Self::Set(els) => {
+                // Equal sets can iterate in different orders, so the element hashes are combined
+                // in a way that doesn't depend on order
+                let combined = els.iter().fold(0u64, |acc, el| {
+                    let mut hasher = DefaultHasher::new();
+                    el.hash(&mut hasher);
+                    acc.wrapping_add(hasher.finish())
+                });
+                combined.hash(state);
+            }
+            Self::Function { ins, locals, req_params, opt_params, locked_values } => {
+                ins.hash(state);
+                locals.hash(state);
+                req_params.hash(state);
+                opt_params.hash(state);
+                locked_values.hash(state);
+            }
+            Self::NativeFunction(native) => native.hash(state),
+        }
+    }
+}
+
 impl Debug for BaseObject {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -114,47 +205,157 @@ impl Debug for BaseObject {
             Self::Tuple(els) => f.debug_tuple("tup").field(els).finish(),
             Self::Set(els) => f.debug_tuple("set").field(els).finish(),
             Self::Function {locked_values, ..} => f.debug_tuple("fn").field(locked_values).finish(),
+            Self::NativeFunction(native) => native.fmt(f),
         }
     }
 }
 
-pub struct Object {
-    pub inner: Rc<BaseObject>
+/**
+ * A value as it lives on the VM's stack. Null, booleans and numbers are stored inline so that
+ * pushing them never allocates, while strings, collections and functions are reference counted.
+ * Scalars are never boxed, so two equal values always share the same variant.
+ */
+#[derive(Clone)]
+pub enum Object {
+    Null,
+    True,
+    False,
+    Integer(i64),
+    Float(f64),
+    Heap(Rc<BaseObject>),
 }
 
 impl Object {
-    /** Create a new reference to the same inner base object */
+    /** Create a new reference to the same value */
     pub fn reference(&self) -> Object {
-        Object { inner: self.inner.clone() }
+        self.clone()
+    }
+
+    pub fn from_bool(val: bool) -> Object {
+        if val { Object::True } else { Object::False }
+    }
+
+    /** Whether both objects are the same boxed value, rather than merely equal ones */
+    pub fn same_ref(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Heap(a), Object::Heap(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /** The boxed value, for anything that isn't a scalar */
+    pub fn heap(&self) -> Option<&BaseObject> {
+        match self {
+            Object::Heap(inner) => Some(inner.as_ref()),
+            _ => None,
+        }
     }
 }
 
 impl ObjectOps for Object {
     fn not(&self) -> Self {
-        self.inner.not().wrap()
+        match self {
+            Object::True => Object::False,
+            Object::False => Object::True,
+            _ => panic!("NOT operation can only be used on boolean values"),
+        }
     }
 
     fn truthy(&self) -> bool {
-        self.inner.truthy()
+        match self {
+            Object::True => true,
+            Object::False | Object::Null => false,
+            Object::Integer(val) => *val != 0,
+            Object::Float(val) => *val != 0.0,
+            Object::Heap(inner) => inner.truthy(),
+        }
     }
 
     fn is_int(&self) -> bool {
-        self.inner.is_int()
+        matches!(self, Object::Integer(_))
     }
 
     fn get_index(&self, index: &Object) -> Object {
-        self.inner.get_index(index)
+        match self {
+            Object::Heap(inner) => inner.get_index(index),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Object::Null, Object::Null) | (Object::True, Object::True) | (Object::False, Object::False) => true,
+            (Object::Integer(a), Object::Integer(b)) => a == b,
+            (Object::Float(a), Object::Float(b)) => float_eq(*a, *b),
+            (Object::Heap(a), Object::Heap(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// NaN is equal to itself, so equality is reflexive for every value
+impl Eq for Object {}
+
+impl Hash for Object {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Object::Null => BaseObject::Null.hash(state),
+            Object::True => BaseObject::True.hash(state),
+            Object::False => BaseObject::False.hash(state),
+            Object::Integer(val) => BaseObject::Integer(*val).hash(state),
+            Object::Float(val) => BaseObject::Float(*val).hash(state),
+            Object::Heap(inner) => inner.hash(state),
+        }
     }
 }
 
 impl Debug for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        self.inner.fmt(f)
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::True => f.write_str("true"),
+            Self::False => f.write_str("false"),
+            Self::Integer(val) => f.debug_tuple("int").field(val).finish(),
+            Self::Float(val) => f.debug_tuple("float").field(val).finish(),
+            Self::Heap(inner) => Debug::fmt(inner.as_ref(), f),
+        }
     }
 }
 
-impl PartialEq for Object {
-    fn eq(&self, other: &Self) -> bool {
-        self.inner == other.inner
+#[cfg(test)]
+mod tests {
+    use super::{BaseObject, Object};
+
+    #[test]
+    fn scalars_are_inline() {
+        assert_eq!(BaseObject::Null.wrap(), Object::Null);
+        assert_eq!(BaseObject::True.wrap(), Object::True);
+        assert_eq!(BaseObject::Integer(3).wrap(), Object::Integer(3));
+        assert_eq!(BaseObject::Float(1.5).wrap(), Object::Float(1.5));
+        assert!(BaseObject::Integer(3).wrap().heap().is_none());
+
+        let string = BaseObject::String("abc".into()).wrap();
+        assert_eq!(string.heap(), Some(&BaseObject::String("abc".into())));
+        assert_eq!(string, BaseObject::String("abc".into()).wrap());
+        assert_ne!(string, Object::Null);
+    }
+
+    #[test]
+    fn floats_in_sets() {
+        let set = |els: Vec<f64>| BaseObject::Set(els.into_iter().map(Object::Float).collect()).wrap();
+        let zeros = set(vec![0.0, -0.0]);
+        assert_eq!(zeros, set(vec![0.0]));
+        assert_eq!(zeros, set(vec![-0.0]));
+
+        let nans = set(vec![f64::NAN, -f64::NAN, 1.0]);
+        assert_eq!(nans, set(vec![1.0, f64::NAN]));
+        match nans.heap() {
+            Some(BaseObject::Set(els)) => assert!(els.contains(&Object::Float(f64::NAN))),
+            other => panic!("Expected a set, but got {:?}", other),
+        }
+        assert_eq!(Object::Float(f64::NAN), Object::Float(f64::NAN));
+        assert_ne!(Object::Float(f64::NAN), Object::Float(1.0));
     }
 }
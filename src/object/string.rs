@@ -0,0 +1,196 @@
+use std::cell::OnceCell;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+use super::object::{BaseObject, Object};
+use crate::code::code::{self, OpCode};
+use crate::vm::error::RuntimeError;
+
+/**
+ * The text of a YSETL string. Strings are indexed by character rather than by byte, so a string
+ * with characters outside of ASCII keeps the byte offset of each one, worked out the first time
+ * it's needed. That keeps indexing and `#` constant time after the first use.
+ */
+#[derive(Clone)]
+pub struct Str {
+    text: String,
+    /** Byte offset of each character, or `None` for ASCII strings, whose bytes are their characters */
+    offsets: OnceCell<Option<Box<[usize]>>>,
+}
+
+impl Str {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+
+    fn offsets(&self) -> Option<&[usize]> {
+        self.offsets
+            .get_or_init(|| (!self.text.is_ascii()).then(|| self.text.char_indices().map(|(offset, _)| offset).collect()))
+            .as_deref()
+    }
+
+    /** The number of characters in the string */
+    pub fn char_count(&self) -> usize {
+        self.offsets().map_or(self.text.len(), <[usize]>::len)
+    }
+
+    /** The character at `index`, counting characters rather than bytes */
+    pub fn char_at(&self, index: usize) -> Option<&str> {
+        match self.offsets() {
+            None => self.text.get(index..index + 1),
+            Some(offsets) => {
+                let start = *offsets.get(index)?;
+                let end = offsets.get(index + 1).copied().unwrap_or(self.text.len());
+                Some(&self.text[start..end])
+            }
+        }
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl From<String> for Str {
+    fn from(text: String) -> Self {
+        Str { text, offsets: OnceCell::new() }
+    }
+}
+
+impl From<&str> for Str {
+    fn from(text: &str) -> Self {
+        Str::from(text.to_owned())
+    }
+}
+
+// The offsets are only a cache, so two strings are the same if their text is
+impl PartialEq for Str {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state);
+    }
+}
+
+impl Debug for Str {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Debug::fmt(&self.text, f)
+    }
+}
+
+/**
+ * How many characters `left op right` would produce when it builds a string, so it can be checked
+ * against the VM's size limit before anything is allocated. A repetition too long to fit in memory
+ * at all is an error, whatever the limit.
+ */
+pub fn result_len(left: &Object, right: &Object, op: u8) -> Result<Option<usize>, RuntimeError> {
+    match (left.heap(), right.heap(), op) {
+        (Some(BaseObject::String(left)), Some(BaseObject::String(right)), code::Add::VAL) => {
+            Ok(Some(left.char_count().saturating_add(right.char_count())))
+        }
+        _ if op == code::Mult::VAL => {
+            let Some((str, count)) = repetition(left, right) else {
+                return Ok(None);
+            };
+            let count = count.max(0) as usize;
+            // Strings can't take up more than `isize::MAX` bytes
+            match str.len().checked_mul(count).filter(|&bytes| bytes <= isize::MAX as usize) {
+                Some(_) => Ok(Some(str.char_count() * count)),
+                None => Err(RuntimeError::native(format!(
+                    "A string of {} characters repeated {} times is too long",
+                    str.char_count(),
+                    count
+                ))),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/** The string and the count of a repetition, which can be written either way around */
+fn repetition<'a>(left: &'a Object, right: &'a Object) -> Option<(&'a Str, i64)> {
+    match (left, right) {
+        (Object::Heap(inner), Object::Integer(count)) | (Object::Integer(count), Object::Heap(inner)) => match inner.as_ref() {
+            BaseObject::String(str) => Some((str, *count)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/**
+ * `+` for concatenation, `*` with an integer on either side for repetition, and `<` and `<=` for
+ * comparing strings lexicographically, by code point. Anything else isn't a string operation.
+ */
+pub fn string_op(left: &Object, right: &Object, op: u8) -> Option<Object> {
+    let result = match (left.heap(), right.heap()) {
+        (Some(BaseObject::String(left)), Some(BaseObject::String(right))) => match op {
+            code::Add::VAL => BaseObject::String([left.as_str(), right.as_str()].concat().into()),
+            code::Lt::VAL => return Some(Object::from_bool(left.as_str() < right.as_str())),
+            code::Lteq::VAL => return Some(Object::from_bool(left.as_str() <= right.as_str())),
+            _ => return None,
+        },
+        _ if op == code::Mult::VAL => {
+            let (str, count) = repetition(left, right)?;
+            BaseObject::String(str.repeat(count.max(0) as usize).into())
+        }
+        _ => return None,
+    };
+    Some(result.wrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{result_len, string_op, Str};
+    use crate::code::code::{self, OpCode};
+    use crate::object::object::{BaseObject, Object};
+
+    fn string(str: &str) -> Object {
+        BaseObject::String(str.into()).wrap()
+    }
+
+    #[test]
+    fn indexes_by_character() {
+        let ascii = Str::from("abc");
+        assert_eq!((ascii.char_count(), ascii.char_at(1), ascii.char_at(3)), (3, Some("b"), None));
+
+        let wide = Str::from("añ😀z");
+        assert_eq!(wide.char_count(), 4);
+        assert_eq!([wide.char_at(0), wide.char_at(1), wide.char_at(2), wide.char_at(3)], [Some("a"), Some("ñ"), Some("😀"), Some("z")]);
+        assert_eq!(wide.char_at(4), None);
+        assert_eq!(wide, Str::from("añ😀z"));
+    }
+
+    #[test]
+    fn operations() {
+        assert_eq!(string_op(&string("ab"), &string("c"), code::Add::VAL), Some(string("abc")));
+        assert_eq!(string_op(&string("ab"), &Object::Integer(3), code::Mult::VAL), Some(string("ababab")));
+        assert_eq!(string_op(&Object::Integer(-1), &string("ab"), code::Mult::VAL), Some(string("")));
+        assert_eq!(string_op(&string("ab"), &string("b"), code::Lt::VAL), Some(Object::True));
+        assert_eq!(string_op(&string("b"), &string("ab"), code::Lteq::VAL), Some(Object::False));
+        assert_eq!(string_op(&string("ab"), &Object::Float(1.0), code::Mult::VAL), None);
+        assert_eq!(string_op(&string("ab"), &Object::Integer(1), code::Add::VAL), None);
+    }
+
+    #[test]
+    fn result_lengths() {
+        assert_eq!(result_len(&string("añ"), &string("b"), code::Add::VAL), Ok(Some(3)));
+        assert_eq!(result_len(&Object::Integer(3), &string("añ"), code::Mult::VAL), Ok(Some(6)));
+        assert_eq!(result_len(&string("ab"), &Object::Integer(-2), code::Mult::VAL), Ok(Some(0)));
+        assert_eq!(result_len(&Object::Integer(2), &Object::Integer(3), code::Mult::VAL), Ok(None));
+        assert!(result_len(&string("ab"), &Object::Integer(i64::MAX), code::Mult::VAL).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 #[derive(Debug)]
 pub enum BinOp {
     NullCoal,
@@ -102,10 +104,17 @@ pub enum LHS<'a> {
     List(Vec<LHS<'a>>),
 }
 
+/** An expression at the top level of a program or block, along with the line it starts on */
+#[derive(Debug)]
+pub struct Stmt<'a> {
+    pub line: usize,
+    pub expr: ExprST<'a>,
+}
+
 #[derive(Debug)]
 pub struct Case<'a> {
     pub condition: Option<Box<ExprST<'a>>>,
-    pub consequence: Vec<ExprST<'a>>,
+    pub consequence: Vec<Stmt<'a>>,
     pub null_return: bool,
 }
 
@@ -123,7 +132,10 @@ pub enum ExprST<'a> {
     True,
     False,
     Atom(&'a str),
-    String(&'a str),
+    /** A string literal's text, borrowed from the source unless it had escapes to decode */
+    String(Cow<'a, str>),
+    /** A string literal with `${expr}`s in it, as its text and expressions in order */
+    Interpolation(Vec<ExprST<'a>>),
     Ident(&'a str),
     Integer(i64),
     Float(f64),
@@ -133,7 +145,7 @@ pub enum ExprST<'a> {
         req_params: Vec<&'a str>,
         opt_params: Vec<&'a str>,
         locked_params: Vec<&'a str>,
-        body: Vec<ExprST<'a>>,
+        body: Vec<Stmt<'a>>,
         null_return: bool,
     },
     Infix {
@@ -186,5 +198,5 @@ pub enum ExprST<'a> {
 
 pub struct Program<'a> {
     pub name: &'a str,
-    pub expressions: Vec<ExprST<'a>>,
+    pub expressions: Vec<Stmt<'a>>,
 }
@@ -11,9 +11,8 @@ mod tests {
     use pest::Parser;
 
     fn parse_is_ok(rule: Rule, input: &str) {
-        match YsetlParser::parse(rule, input) {
-            Ok(_) => assert!(true),
-            Err(err) => assert!(false, "{:?}", err),
+        if let Err(err) = YsetlParser::parse(rule, input) {
+            panic!("{:?}", err);
         }
     }
 
@@ -37,6 +36,10 @@ mod tests {
         parse_is_ok(Rule::string, "\"hello, world\"");
         parse_is_ok(Rule::string, "\"Hello. \\nWorld.\"");
         parse_is_ok(Rule::string, "\"hello, \\\"world\\\"\"");
+        parse_is_ok(Rule::string, "\"tab\\t, \\u{41} and \\${x}\"");
+        parse_is_ok(Rule::string, "\"x = ${ x + 1 }, ${f(\"y\")}\"");
+        parse_is_ok(Rule::long_string, "\"\"\"\nsay \"hi\"\n\"\"\"");
+        parse_is_ok(Rule::raw_string, "r\"C:\\dir\"");
 
         parse_is_ok(Rule::ident, "foo");
     }
@@ -1,4 +1,5 @@
 pub mod ast;
 pub mod debug;
 pub mod grammar;
+#[allow(clippy::module_inception)]
 pub mod parser;
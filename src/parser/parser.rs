@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use lazy_static;
 use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::PrattParser;
@@ -5,7 +7,7 @@ use pest::Parser;
 
 use super::ast::{
     BinOp, Bound, Case, ExprST, Former, IteratorST, IteratorType, Postfix, PreOp, Program,
-    SelectOp, LHS,
+    SelectOp, Stmt, LHS,
 };
 use super::debug::pair_str;
 use super::grammar::Rule;
@@ -61,13 +63,14 @@ lazy_static::lazy_static! {
     };
 }
 
-pub fn parse_from_expr(input: &str) -> ExprResult {
+pub fn parse_from_expr(input: &str) -> ExprResult<'_> {
     let expr = YsetlParser::parse(Rule::expr, input).unwrap().next().unwrap();
     parse_expr(expr)
 }
 
-pub fn parse_from_program(input: &str) -> Result<Program, String> {
-    let program = YsetlParser::parse(Rule::program_input, &input).unwrap()
+pub fn parse_from_program(input: &str) -> Result<Program<'_>, String> {
+    let program = YsetlParser::parse(Rule::program_input, input)
+        .map_err(|err| err.to_string())?
         .next()
         .unwrap();
 
@@ -78,10 +81,10 @@ pub fn parse_from_program(input: &str) -> Result<Program, String> {
             let name_node = inner.next().unwrap();
             let program_name = atom_value(name_node);
 
-            let mut expressions: Vec<ExprST> = vec![];
+            let mut expressions: Vec<Stmt> = vec![];
             
             for pair in inner {
-                expressions.push(parse_expr(pair)?);
+                expressions.push(parse_stmt(pair)?);
             }
             Ok(Program {
                 name: program_name,
@@ -93,12 +96,78 @@ pub fn parse_from_program(input: &str) -> Result<Program, String> {
     }
 }
 
-fn atom_value(atom_pair: Pair<Rule>) -> &str {
+/** Parses statements separated by semicolons, as in the body of a program but without its header */
+pub fn parse_from_statements(input: &str) -> Result<Vec<Stmt<'_>>, String> {
+    let statements = YsetlParser::parse(Rule::statements_input, input).map_err(|err| err.to_string())?;
+    statements
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(parse_stmt)
+        .collect()
+}
+
+fn atom_value(atom_pair: Pair<'_, Rule>) -> &str {
     atom_pair.into_inner().next().unwrap().as_str()
 }
 
-fn string_value(string_pair: Pair<Rule>) -> &str {
-    string_pair.into_inner().next().unwrap().as_str()
+/** The character a backslash escape stands for, such as `\n` or `\u{1F600}` */
+fn escape_value(escape: &str) -> Result<char, String> {
+    match &escape[1..] {
+        "n" => Ok('\n'),
+        "t" => Ok('\t'),
+        "r" => Ok('\r'),
+        code if code.starts_with("u{") => {
+            let hex = &code[2..code.len() - 1];
+            u32::from_str_radix(hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("{} is not a valid character", escape))
+        }
+        // The grammar only allows escaping quotes, backslashes and dollar signs otherwise
+        other => Ok(other.chars().next().unwrap()),
+    }
+}
+
+/**
+ * Builds a string literal from its parts. Text without escapes is borrowed from the source, and a
+ * literal with interpolated expressions becomes an `Interpolation` of its text and expressions.
+ */
+fn parse_string(string_pair: Pair<Rule>) -> ExprResult {
+    let long = string_pair.as_rule() == Rule::long_string;
+    let mut parts = vec![];
+    let mut text: Option<Cow<str>> = None;
+    for (i, part) in string_pair.into_inner().enumerate() {
+        match part.as_rule() {
+            Rule::string_text | Rule::long_string_text | Rule::raw_string_text => {
+                let mut str = part.as_str();
+                if long && i == 0 {
+                    // A long string can start on the line after its opening quotes
+                    str = str.strip_prefix('\n').or_else(|| str.strip_prefix("\r\n")).unwrap_or(str);
+                }
+                match &mut text {
+                    Some(text) => text.to_mut().push_str(str),
+                    None => text = Some(Cow::Borrowed(str)),
+                }
+            }
+            Rule::string_escape => {
+                let ch = escape_value(part.as_str())?;
+                text.get_or_insert_with(|| Cow::Owned(String::new())).to_mut().push(ch);
+            }
+            Rule::interpolation => {
+                if let Some(text) = text.take() {
+                    parts.push(ExprST::String(text));
+                }
+                parts.push(parse_expr(part.into_inner().next().unwrap())?);
+            }
+            rule => unreachable!("parse_string expected part of a string, received {:?}", rule),
+        }
+    }
+    if parts.is_empty() {
+        return Ok(ExprST::String(text.unwrap_or_default()));
+    }
+    if let Some(text) = text {
+        parts.push(ExprST::String(text));
+    }
+    Ok(ExprST::Interpolation(parts))
 }
 
 fn number_value(number_pair: Pair<Rule>) -> ExprST {
@@ -119,12 +188,12 @@ fn construct_number<'a>(base: &str, decimal: &str, exp: &str) -> ExprST<'a> {
     let mut is_float = false;
     let mut number_str = base.to_owned();
 
-    if decimal != "" {
+    if !decimal.is_empty() {
         is_float = true;
         number_str.push_str(decimal)
     }
 
-    if exp != "" {
+    if !exp.is_empty() {
         is_float = true;
         number_str.push('e');
         number_str.push_str(&exp[1..]);
@@ -153,7 +222,7 @@ fn pull_param_type<'a>(rule: Rule, params: &mut Pairs<'a, Rule>) -> Vec<&'a str>
 
 struct ParamLists<'a>(Vec<&'a str>, Vec<&'a str>, Vec<&'a str>);
 
-fn parse_param_list<'a>(param_list: Pair<'a, Rule>) -> Result<ParamLists, String> {
+fn parse_param_list<'a>(param_list: Pair<'a, Rule>) -> Result<ParamLists<'a>, String> {
     let mut params = param_list.into_inner();
 
     let req_params = pull_param_type(Rule::req_param, &mut params);
@@ -170,7 +239,15 @@ fn parse_param_list<'a>(param_list: Pair<'a, Rule>) -> Result<ParamLists, String
     }
 }
 
-fn drain_block<'a>(mut parts: Pairs<'a, Rule>) -> (Vec<ExprST<'a>>, bool) {
+fn parse_stmt(input: Pair<Rule>) -> Result<Stmt, String> {
+    let line = input.line_col().0;
+    Ok(Stmt {
+        line,
+        expr: parse_expr(input)?,
+    })
+}
+
+fn drain_block<'a>(mut parts: Pairs<'a, Rule>) -> (Vec<Stmt<'a>>, bool) {
     let mut body = Vec::new();
     while let Some(expr) = parts.peek().and_then(|part_type| {
         if part_type.as_rule() != Rule::captured_semicolon {
@@ -179,7 +256,7 @@ fn drain_block<'a>(mut parts: Pairs<'a, Rule>) -> (Vec<ExprST<'a>>, bool) {
             None
         }
     }) {
-        body.push(parse_expr(expr).unwrap())
+        body.push(parse_stmt(expr).unwrap())
     }
 
     // After exhausting the iterator above, there's either a captured semicolon or nothing
@@ -251,7 +328,7 @@ fn parse_infix<'a>(lhs: ExprResult<'a>, rhs: ExprResult<'a>, op_rule: Rule) -> E
         },
         |op| {
             Ok(ExprST::Infix {
-                op: op,
+                op,
                 left: Box::new(lhs?),
                 right: Box::new(rhs?),
             })
@@ -280,11 +357,11 @@ fn parse_reduce_expr<'a>(
             left,
             right,
         }),
-        bin_op => to_binop(bin_op).map_or_else(
+        op_rule => to_binop(op_rule).map_or_else(
             || {
                 Err(format!(
                     "parse_reduce_expr expected infix operator, received {:?}",
-                    bin_op
+                    op_rule
                 ))
             },
             |op| Ok(ExprST::ReduceWithOp { op, left, right }),
@@ -379,9 +456,9 @@ fn parse_former<'a>(mut former: Pairs<'a, Rule>) -> Former<'a> {
                 }
                 Rule::range_former => {
                     let range_start = unwrap_range(former_parts.next().unwrap())
-                        .expect(&format!("Range in collection former must be well defined"));
+                        .expect("Range in collection former must be well defined");
                     let range_end = unwrap_range(former_parts.next().unwrap())
-                        .expect(&format!("Range in collection former must be well defined"));
+                        .expect("Range in collection former must be well defined");
                     Former::Range {
                         range_start,
                         range_step: None,
@@ -392,10 +469,10 @@ fn parse_former<'a>(mut former: Pairs<'a, Rule>) -> Former<'a> {
                     let range_start = Box::new(parse_expr(former_parts.next().unwrap()).unwrap());
                     let range_step = Some(
                         unwrap_range(former_parts.next().unwrap())
-                            .expect(&format!("Range in collection former must be well defined")),
+                            .expect("Range in collection former must be well defined"),
                     );
                     let range_end = unwrap_range(former_parts.next().unwrap())
-                        .expect(&format!("Range in collection former must be well defined"));
+                        .expect("Range in collection former must be well defined");
                     Former::Range {
                         range_start,
                         range_step,
@@ -420,7 +497,7 @@ fn map_primary_to_expr(primary: Pair<Rule>) -> ExprResult {
         Rule::true_ => Ok(ExprST::True),
         Rule::false_ => Ok(ExprST::False),
         Rule::atom => Ok(ExprST::Atom(atom_value(primary))),
-        Rule::string => Ok(ExprST::String(string_value(primary))),
+        Rule::string | Rule::long_string | Rule::raw_string => parse_string(primary),
         Rule::ident => Ok(ExprST::Ident(primary.as_str())),
         Rule::number => Ok(number_value(primary)),
         Rule::tuple_literal => Ok(ExprST::TupleLiteral(parse_former(primary.into_inner()))),
@@ -432,7 +509,7 @@ fn map_primary_to_expr(primary: Pair<Rule>) -> ExprResult {
     }
 }
 
-fn unwrap_expr_list<'a>(list: Pair<'a, Rule>) -> Vec<ExprST> {
+fn unwrap_expr_list<'a>(list: Pair<'a, Rule>) -> Vec<ExprST<'a>> {
     list.into_inner()
         .map(|part| parse_expr(part).unwrap())
         .collect::<Vec<_>>()
@@ -0,0 +1,310 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::code::code::{self, OpCode};
+use crate::code::debug::lookup;
+use crate::code::words::Code;
+use crate::object::object::BaseObject;
+use crate::vm::debug::{DebugHook, DebugState};
+
+/*
+ * Records where a program spends its time, driven by the VM's debug hook:
+ * - How many times each opcode runs
+ * - For each function, how many times it's called and how long it runs for. Inclusive time counts
+ *   the functions it calls, while exclusive time doesn't. Time spent in a recursive call is only
+ *   counted once towards the inclusive time.
+ * - How many tuples and sets are allocated, including the copies made when updating a collection
+ *   that's shared
+ *
+ * Functions are told apart by their code, so every closure made from the same function constant
+ * is counted together. They're labelled with the name they were assigned to and the line they're
+ * defined on.
+ */
+
+/** Reads a profile that a `Profiler` is filling in, which can be done once the program is over */
+pub type ProfileHandle = Rc<RefCell<Profile>>;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FunctionStats {
+    calls: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+}
+
+/** A function that's running, on the profiler's copy of the call stack */
+struct Entry {
+    function: usize,
+    start: Instant,
+    children: Duration,
+}
+
+/** Which function in `labels` a frame's code belongs to, identified by the code's address */
+fn function_key(code: &Code) -> usize {
+    code as *const Code as usize
+}
+
+fn collection_ptr(state: &DebugState, from_top: usize) -> Option<*const BaseObject> {
+    let stack = state.stack();
+    stack.len().checked_sub(from_top + 1).and_then(|index| stack[index].heap()).map(|obj| obj as *const BaseObject)
+}
+
+#[derive(Default)]
+pub struct Profile {
+    opcodes: HashMap<u8, u64>,
+    /** Indices into `labels` and `stats`, by function key */
+    functions: HashMap<usize, usize>,
+    labels: Vec<String>,
+    stats: Vec<FunctionStats>,
+    calls: Vec<Entry>,
+    /** Exclusive time for each distinct stack of functions, as indices joined by `;` */
+    folded: HashMap<Vec<usize>, Duration>,
+    tuples_allocated: u64,
+    sets_allocated: u64,
+
+    /** The opcode that's running, and the collection it may be updating */
+    current: Option<(u8, usize, Option<*const BaseObject>)>,
+}
+
+impl Profile {
+    fn function_index(&mut self, state: &DebugState) -> usize {
+        let depth = state.depth();
+        let code = state.code(depth);
+        let next_index = self.labels.len();
+        let index = *self.functions.entry(function_key(code)).or_insert(next_index);
+        if index == next_index {
+            let info = code.debug_info();
+            let name = match &info.name {
+                Some(name) => name.as_str(),
+                None if depth == 0 => "<main>",
+                None => "<anonymous>",
+            };
+            self.labels.push(match info.line {
+                Some(line) => format!("{} (line {})", name, line),
+                None => name.to_owned(),
+            });
+            self.stats.push(FunctionStats::default());
+        }
+        index
+    }
+
+    fn enter(&mut self, state: &DebugState) {
+        let function = self.function_index(state);
+        self.stats[function].calls += 1;
+        self.calls.push(Entry {
+            function,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn leave(&mut self) {
+        let path: Vec<usize> = self.calls.iter().map(|entry| entry.function).collect();
+        let entry = self.calls.pop().unwrap();
+        let inclusive = entry.start.elapsed();
+        let exclusive = inclusive.saturating_sub(entry.children);
+
+        let stats = &mut self.stats[entry.function];
+        stats.exclusive += exclusive;
+        if !self.calls.iter().any(|caller| caller.function == entry.function) {
+            stats.inclusive += inclusive;
+        }
+        *self.folded.entry(path).or_default() += exclusive;
+        if let Some(caller) = self.calls.last_mut() {
+            caller.children += inclusive;
+        }
+    }
+
+    /** Stops the clock on every function still running, for when the program has ended */
+    pub fn finish(&mut self) {
+        while !self.calls.is_empty() {
+            self.leave();
+        }
+    }
+
+    /** The profile as tables of functions by exclusive time, and of opcodes by how often they ran */
+    pub fn table(&self) -> String {
+        let mut output = String::new();
+        let mut functions: Vec<(&String, &FunctionStats)> = self.labels.iter().zip(&self.stats).collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then_with(|| a.0.cmp(b.0)));
+        let width = functions.iter().map(|(label, _)| label.len()).max().unwrap_or(0).max("function".len());
+        writeln!(output, "{:<width$} {:>10} {:>14} {:>14}", "function", "calls", "inclusive", "exclusive").unwrap();
+        for (label, stats) in functions {
+            writeln!(
+                output,
+                "{:<width$} {:>10} {:>12.3}ms {:>12.3}ms",
+                label,
+                stats.calls,
+                stats.inclusive.as_secs_f64() * 1000.0,
+                stats.exclusive.as_secs_f64() * 1000.0,
+            )
+            .unwrap();
+        }
+
+        let mut opcodes: Vec<(&str, u64)> = self
+            .opcodes
+            .iter()
+            .map(|(op, count)| (lookup(*op).map_or("?", |(_, name)| name), *count))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        writeln!(output, "\n{:<16} {:>12}", "opcode", "count").unwrap();
+        for (name, count) in opcodes {
+            writeln!(output, "{:<16} {:>12}", name, count).unwrap();
+        }
+
+        writeln!(output, "\ntuples allocated: {}", self.tuples_allocated).unwrap();
+        writeln!(output, "sets allocated: {}", self.sets_allocated).unwrap();
+        output
+    }
+
+    /** Exclusive time in nanoseconds for each stack of functions, in the folded format flamegraph tools read */
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .folded
+            .iter()
+            .map(|(path, time)| {
+                let labels: Vec<&str> = path.iter().map(|function| self.labels[*function].as_str()).collect();
+                format!("{} {}", labels.join(";"), time.as_nanos())
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    pub fn opcode_count(&self, op: u8) -> u64 {
+        self.opcodes.get(&op).copied().unwrap_or(0)
+    }
+
+    pub fn calls(&self, label: &str) -> Option<u64> {
+        self.labels.iter().position(|l| l == label).map(|index| self.stats[index].calls)
+    }
+
+    pub fn allocations(&self) -> (u64, u64) {
+        (self.tuples_allocated, self.sets_allocated)
+    }
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    profile: ProfileHandle,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn profile(&self) -> ProfileHandle {
+        self.profile.clone()
+    }
+}
+
+impl DebugHook for Profiler {
+    fn before_instruction(&mut self, state: &DebugState) {
+        let mut profile = self.profile.borrow_mut();
+        if profile.calls.is_empty() {
+            profile.enter(state);
+        }
+        let op = state.word().op;
+        *profile.opcodes.entry(op).or_default() += 1;
+
+        // Updates leave their result where the collection being updated was
+        let target = match op {
+            code::With::VAL
+            | code::Less::VAL
+            | code::Union::VAL
+            | code::WithGVar::VAL
+            | code::WithLVar::VAL
+            | code::LessGVar::VAL
+            | code::LessLVar::VAL => collection_ptr(state, 1),
+            code::SetIndex::VAL | code::SetIndexGVar::VAL | code::SetIndexLVar::VAL => collection_ptr(state, 2),
+            _ => None,
+        };
+        profile.current = Some((op, state.depth(), target));
+    }
+
+    fn after_instruction(&mut self, state: &DebugState) {
+        let mut profile = self.profile.borrow_mut();
+        let Some((op, depth, target)) = profile.current.take() else {
+            return;
+        };
+        // Native functions run without a frame of their own, and aren't profiled
+        let entered = state.position(state.depth()) == 0;
+        match op {
+            code::Call::VAL if entered => profile.enter(state),
+            // A tail call replaces the caller, unless it's made from the main program or the caller
+            // was called back from a native
+            code::TailCall::VAL if entered => {
+                if state.depth() == depth && profile.calls.len() > depth {
+                    profile.leave();
+                }
+                profile.enter(state);
+            }
+            // Functions called back from a native are run without a Call, and counted as part of it
+            code::Return::VAL | code::ReturnNull::VAL if profile.calls.len() > state.depth() + 1 => profile.leave(),
+            code::ToTuple::VAL | code::ToTupleRn::VAL => profile.tuples_allocated += 1,
+            code::ToSet::VAL | code::ToSetRn::VAL => profile.sets_allocated += 1,
+            _ if target.is_some() => {
+                let result = state.stack().last().and_then(|obj| obj.heap());
+                if result.is_some_and(|obj| Some(obj as *const BaseObject) != target) {
+                    match result {
+                        Some(BaseObject::Tuple(_)) => profile.tuples_allocated += 1,
+                        Some(BaseObject::Set(_)) => profile.sets_allocated += 1,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use crate::code::code::{self, OpCode};
+    use crate::compiler::compiler::Compiler;
+    use crate::parser::parser;
+    use crate::vm::vm::VM;
+
+    #[test]
+    fn counts_calls_and_allocations() {
+        let source = "program :profile;
+fib = 0;
+fib = func(n) { if n < 2 ? n : fib(n - 1) + fib(n - 2) };
+count = 0;
+count = func(n, s) { if n == 0 ? s : count(n - 1, s with n) };
+fib(10);
+kept = {1};
+copy = kept with 2;
+count(5, {});
+[1, 2];";
+        let mut compiler = Compiler::new();
+        compiler.compile_program(parser::parse_from_program(source).unwrap());
+        let profiler = Profiler::new();
+        let profile = profiler.profile();
+        let mut vm = VM::new(compiler.finish());
+        vm.set_debug_hook(Box::new(profiler));
+        vm.run().unwrap();
+        profile.borrow_mut().finish();
+
+        let profile = profile.borrow();
+        assert_eq!(profile.calls("<main>"), Some(1));
+        assert_eq!(profile.calls("fib (line 3)"), Some(177));
+        // One regular call from the main program, then each tail call counts as a call of its own
+        assert_eq!(profile.calls("count (line 5)"), Some(6));
+        assert_eq!(profile.opcode_count(code::Call::VAL), 178);
+
+        // `{1}`, `{}`, and `[1, 2]` are built, and `kept with 2` copies a set that's still held by
+        // `kept`. Each `s with n` is passed on by a tail call, so it updates the set in place.
+        assert_eq!(profile.allocations(), (1, 3));
+
+        let table = profile.table();
+        assert!(table.starts_with("function"));
+        assert!(table.contains("tuples allocated: 1"));
+        let folded = profile.folded();
+        assert!(folded.lines().any(|line| line.starts_with("<main>;fib (line 3);fib (line 3) ")));
+    }
+}
@@ -0,0 +1,293 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::object::display::push_text;
+use crate::object::object::{BaseObject, Object};
+use crate::vm::error::RuntimeError;
+use crate::vm::native::{NativeFunction, VmCtx};
+
+/*
+ * Builtins for the program's input and output, which go through the VM's streams so a host can
+ * capture them:
+ *
+ * print(values...)           Writes the values separated by spaces, then a newline
+ * write(values...)           Writes the values separated by spaces
+ * read_line()                Reads a line, without its line break, or null at the end of the input
+ * read_all()                 Reads the rest of the input
+ * format(template, args...)  Fills each `{}` in the template with the next arg, and each `{N}` with
+ *                            the Nth one, counting from 0. `{{` and `}}` stand for the braces.
+ *
+ * Strings are written as their contents, and everything else as it would be written in source.
+ *
+ * And for files, which may only be used within the directories the VM's `FileAccess` allows:
+ *
+ * read_file(path)            Reads a whole file as a string
+ * read_lines(path)           Reads a file as a tuple of its lines, without their line breaks
+ * write_file(path, text)     Replaces a file's contents with a string, creating it if needed
+ * append_file(path, text)    Adds a string to the end of a file, creating it if needed
+ * list_dir(path)             The names of the entries in a directory, as a sorted tuple
+ */
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("print", 0, None, |ctx, args| write_values(ctx, args, "print", "\n")),
+        NativeFunction::new("write", 0, None, |ctx, args| write_values(ctx, args, "write", "")),
+        NativeFunction::new("read_line", 0, Some(0), read_line),
+        NativeFunction::new("read_all", 0, Some(0), read_all),
+        NativeFunction::new("format", 1, None, format),
+        NativeFunction::new("read_file", 1, Some(1), read_file),
+        NativeFunction::new("read_lines", 1, Some(1), read_lines),
+        NativeFunction::new("write_file", 2, Some(2), |ctx, args| write_file(ctx, args, "write_file", false)),
+        NativeFunction::new("append_file", 2, Some(2), |ctx, args| write_file(ctx, args, "append_file", true)),
+        NativeFunction::new("list_dir", 1, Some(1), list_dir),
+    ]
+}
+
+fn io_error(function: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::native(format!("{} failed: {}", function, err))
+}
+
+fn string_arg<'a>(args: &'a [Object], index: usize, function: &str) -> Result<&'a str, RuntimeError> {
+    match args[index].heap() {
+        Some(BaseObject::String(str)) => Ok(str),
+        _ => Err(RuntimeError::native(format!("{} needs a string, but was given {}", function, args[index]))),
+    }
+}
+
+fn readable_path(ctx: &VmCtx, args: &[Object], function: &str) -> Result<PathBuf, RuntimeError> {
+    let path = string_arg(args, 0, function)?;
+    ctx.file_access().check_read(path).map_err(|err| RuntimeError::native(format!("{}: {}", function, err)))
+}
+
+fn write_values(ctx: &mut VmCtx, args: &[Object], function: &str, end: &str) -> Result<Object, RuntimeError> {
+    let mut text = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            text.push(' ');
+        }
+        push_text(&mut text, arg);
+    }
+    text.push_str(end);
+    let output = ctx.output();
+    // Flushing shows output without a line break straight away, such as a prompt for input
+    output.write_all(text.as_bytes()).and_then(|_| output.flush()).map_err(|err| io_error(function, err))?;
+    Ok(Object::Null)
+}
+
+fn read_line(ctx: &mut VmCtx, _args: &[Object]) -> Result<Object, RuntimeError> {
+    let mut line = String::new();
+    if ctx.input().read_line(&mut line).map_err(|err| io_error("read_line", err))? == 0 {
+        return Ok(Object::Null);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(BaseObject::String(line.into()).wrap())
+}
+
+fn read_all(ctx: &mut VmCtx, _args: &[Object]) -> Result<Object, RuntimeError> {
+    let mut text = String::new();
+    ctx.input().read_to_string(&mut text).map_err(|err| io_error("read_all", err))?;
+    Ok(BaseObject::String(text.into()).wrap())
+}
+
+fn read_file(ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+    let path = readable_path(ctx, args, "read_file")?;
+    let text = fs::read_to_string(path).map_err(|err| io_error("read_file", err))?;
+    Ok(BaseObject::String(text.into()).wrap())
+}
+
+fn read_lines(ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+    let path = readable_path(ctx, args, "read_lines")?;
+    let text = fs::read_to_string(path).map_err(|err| io_error("read_lines", err))?;
+    let lines: Vec<&str> = text.lines().collect();
+    ctx.check_collection_size(lines.len())?;
+    Ok(BaseObject::Tuple(lines.into_iter().map(|line| BaseObject::String(line.into()).wrap()).collect()).wrap())
+}
+
+fn write_file(ctx: &mut VmCtx, args: &[Object], function: &str, append: bool) -> Result<Object, RuntimeError> {
+    let path = string_arg(args, 0, function)?;
+    let text = string_arg(args, 1, function)?;
+    let path = ctx
+        .file_access()
+        .check_write(path)
+        .map_err(|err| RuntimeError::native(format!("{}: {}", function, err)))?;
+    let file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path);
+    file.and_then(|mut file| file.write_all(text.as_bytes())).map_err(|err| io_error(function, err))?;
+    Ok(Object::Null)
+}
+
+fn list_dir(ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+    let path = readable_path(ctx, args, "list_dir")?;
+    let entries = fs::read_dir(path).and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>());
+    let mut names: Vec<String> = entries
+        .map_err(|err| io_error("list_dir", err))?
+        .iter()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    ctx.check_collection_size(names.len())?;
+    Ok(BaseObject::Tuple(names.into_iter().map(|name| BaseObject::String(name.into()).wrap()).collect()).wrap())
+}
+
+fn format(ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+    let Some(BaseObject::String(template)) = args[0].heap() else {
+        return Err(RuntimeError::native(format!("format needs a string template, but was given {}", args[0])));
+    };
+    let values = &args[1..];
+    let mut output = String::new();
+    // Characters in the output so far, up to the byte offset `counted`
+    let (mut len, mut counted) = (0, 0);
+    let mut next_value = 0;
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('{') if placeholder.is_empty() => {
+                            output.push('{');
+                            break;
+                        }
+                        Some('}') => {
+                            let index = if placeholder.is_empty() {
+                                next_value += 1;
+                                next_value - 1
+                            } else {
+                                placeholder.parse().map_err(|_| {
+                                    RuntimeError::native(format!("format can't fill in {{{}}}", placeholder))
+                                })?
+                            };
+                            let value = values.get(index).ok_or_else(|| {
+                                RuntimeError::native(format!("format has no arg {} for its template", index))
+                            })?;
+                            push_text(&mut output, value);
+                            // The template's own text can't go far past the limit, but filled in values can
+                            len += output[counted..].chars().count();
+                            counted = output.len();
+                            ctx.check_collection_size(len)?;
+                            break;
+                        }
+                        Some(ch) => placeholder.push(ch),
+                        None => return Err(RuntimeError::native("format's template has an unclosed {")),
+                    }
+                }
+            }
+            '}' if chars.next() == Some('}') => output.push('}'),
+            '}' => return Err(RuntimeError::native("format's template has a } that doesn't close anything")),
+            ch => output.push(ch),
+        }
+    }
+    ctx.check_collection_size(len + output[counted..].chars().count())?;
+    Ok(BaseObject::String(output.into()).wrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpreter::{Error, Interpreter};
+    use crate::interpreter::value::Value;
+    use crate::vm::error::RuntimeError;
+    use crate::test_util::SharedOutput;
+    use crate::vm::sandbox::FileAccess;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn interpreter(input: &'static str) -> (Interpreter, SharedOutput) {
+        let mut interpreter = Interpreter::new();
+        let output = SharedOutput::default();
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.set_input(Box::new(Cursor::new(input)));
+        (interpreter, output)
+    }
+
+    #[test]
+    fn writes_values() {
+        let (mut interpreter, output) = interpreter("");
+        let result = interpreter.eval("print(\"total:\", 1 + 2, [1.0, \"a\"], {}); write(\"> \"); write(null, true); print();");
+        assert_eq!(result.unwrap(), Value::Null);
+        assert_eq!(output.text(), "total: 3 [1.0, \"a\"] {}\n> null true\n");
+    }
+
+    #[test]
+    fn reads_input() {
+        let (mut interpreter, _) = interpreter("first\r\nsecond\nthe\nrest");
+        let lines = interpreter.eval("[read_line(), read_line(), read_all(), read_line()]").unwrap();
+        let expected = vec!["first".into(), "second".into(), "the\nrest".into(), Value::Null];
+        assert_eq!(lines, Value::Tuple(expected));
+    }
+
+    #[test]
+    fn formats() {
+        let (mut interpreter, _) = interpreter("");
+        let mut format = |source| interpreter.eval(source);
+        assert_eq!(format("format(\"{} + {} = {}\", 1, 2.5, [3])").unwrap(), "1 + 2.5 = [3]".into());
+        assert_eq!(format("format(\"{1}{0}{1} {{{}}}\", \"a\", \"b\")").unwrap(), "bab {a}".into());
+        assert_eq!(format("format(\"no placeholders\")").unwrap(), "no placeholders".into());
+
+        let mut error = |source| match format(source) {
+            Err(Error::Runtime(RuntimeError::Native(message))) => message,
+            other => panic!("Expected an error from {}, but got {:?}", source, other),
+        };
+        assert_eq!(error("format(\"{} {}\", 1)"), "format has no arg 1 for its template");
+        assert_eq!(error("format(\"{x}\", 1)"), "format can't fill in {x}");
+        assert_eq!(error("format(\"{\")"), "format's template has an unclosed {");
+        assert_eq!(error("format(\"}\")"), "format's template has a } that doesn't close anything");
+        assert_eq!(error("format(1)"), "format needs a string template, but was given 1");
+    }
+
+    #[test]
+    fn files() {
+        let root = std::env::temp_dir().join(format!("ysetl-files-{}", std::process::id()));
+        let inputs = root.join("inputs");
+        let outputs = root.join("outputs");
+        fs::create_dir_all(&inputs).unwrap();
+        fs::create_dir_all(&outputs).unwrap();
+        fs::write(inputs.join("day1.txt"), "12\n34\n").unwrap();
+        fs::write(inputs.join("day2.txt"), "").unwrap();
+        fs::write(root.join("secret.txt"), "hidden").unwrap();
+
+        let (mut interpreter, _) = interpreter("");
+        let mut access = FileAccess::default();
+        access.allow_read(&inputs).unwrap();
+        access.allow_read(&outputs).unwrap();
+        access.allow_write(&outputs).unwrap();
+        interpreter.set_file_access(access);
+        interpreter.set_global("inputs", inputs.to_str().unwrap());
+        interpreter.set_global("outputs", outputs.to_str().unwrap());
+        let mut run = |source: &str| interpreter.eval(source).map_err(|err| err.to_string());
+
+        assert_eq!(
+            run("day1 = format(\"{}/day1.txt\", inputs); [read_file(day1), read_lines(day1), list_dir(inputs)]"),
+            Ok(Value::Tuple(vec![
+                "12\n34\n".into(),
+                Value::Tuple(vec!["12".into(), "34".into()]),
+                Value::Tuple(vec!["day1.txt".into(), "day2.txt".into()]),
+            ]))
+        );
+        let out = "format(\"{}/out.txt\", outputs)";
+        run(&format!("write_file({out}, \"a\"); append_file({out}, \"b\"); append_file({out}, \"c\");")).unwrap();
+        assert_eq!(run(&format!("read_file({out})")), Ok("abc".into()));
+        run(&format!("write_file({out}, \"replaced\");")).unwrap();
+        assert_eq!(fs::read_to_string(outputs.join("out.txt")).unwrap(), "replaced");
+
+        // Paths are checked after following `..`, and reading doesn't allow writing
+        let denied = |function: &str, path: &str, access: &str| {
+            Err(format!("Runtime error: {function}: {path} is outside of the directories allowed for {access}"))
+        };
+        let secret = format!("{}/../secret.txt", inputs.to_str().unwrap());
+        assert_eq!(run(&format!("read_file(\"{secret}\")")), denied("read_file", &secret, "reading"));
+        assert_eq!(run("list_dir(\"/\")"), denied("list_dir", "/", "reading"));
+        let input = format!("{}/new.txt", inputs.to_str().unwrap());
+        assert_eq!(run(&format!("write_file(\"{input}\", \"x\")")), denied("write_file", &input, "writing"));
+        assert!(!inputs.join("new.txt").exists());
+        assert!(run("read_file(format(\"{}/missing.txt\", inputs))").unwrap_err().starts_with("Runtime error: read_file failed:"));
+        assert_eq!(run("read_file(1)"), Err("Runtime error: read_file needs a string, but was given 1".to_owned()));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
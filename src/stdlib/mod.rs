@@ -0,0 +1,11 @@
+pub mod io;
+pub mod strings;
+
+use crate::vm::native::NativeFunction;
+
+/** Every native function that YSETL programs can use by default */
+pub fn natives() -> Vec<NativeFunction> {
+    let mut natives = io::natives();
+    natives.extend(strings::natives());
+    natives
+}
@@ -0,0 +1,87 @@
+use crate::object::display;
+use crate::object::object::{BaseObject, Object};
+use crate::vm::error::RuntimeError;
+use crate::vm::native::{NativeFunction, VmCtx};
+
+/*
+ * Builtins that turn values into strings:
+ *
+ * str(value)             The value as `print` would write it: strings as their contents, and
+ *                        everything else as it would be written in source
+ * pretty(value, width?)  The value as it would be written in source, broken across lines so that
+ *                        each line fits in `width` columns where it can. The width defaults to 80.
+ */
+
+const DEFAULT_WIDTH: i64 = 80;
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("str", 1, Some(1), to_str),
+        NativeFunction::new("pretty", 1, Some(2), pretty),
+    ]
+}
+
+/** Hands back a string the builtin made, as long as it's within the VM's size limit */
+fn checked_string(ctx: &VmCtx, text: String) -> Result<Object, RuntimeError> {
+    ctx.check_collection_size(text.chars().count())?;
+    Ok(BaseObject::String(text.into()).wrap())
+}
+
+fn to_str(ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+    if let Some(BaseObject::String(_)) = args[0].heap() {
+        return Ok(args[0].reference());
+    }
+    let mut text = String::new();
+    display::push_text(&mut text, &args[0]);
+    checked_string(ctx, text)
+}
+
+fn pretty(ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+    let width = match args.get(1) {
+        None | Some(Object::Null) => DEFAULT_WIDTH,
+        Some(&Object::Integer(width)) if width >= 0 => width,
+        Some(other) => return Err(RuntimeError::native(format!("pretty needs a width of at least 0, but was given {}", other))),
+    };
+    checked_string(ctx, display::pretty(&args[0], width as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::value::Value;
+    use crate::vm::limits::VmLimits;
+
+    fn eval(source: &str) -> Value {
+        Interpreter::new().eval(source).unwrap()
+    }
+
+    #[test]
+    fn converts_to_strings() {
+        assert_eq!(eval("str(\"as is\")"), Value::from("as is"));
+        assert_eq!(eval("str([1, 2.5, \"a\", null])"), Value::from("[1, 2.5, \"a\", null]"));
+        assert_eq!(eval("str({3, 1, 2})"), Value::from("{1, 2, 3}"));
+        assert_eq!(eval("str(func(a, b, c?) { a })"), Value::from("func(a, b, c?)"));
+        assert_eq!(eval("t = 1; str(func(x, t!) { x + t })"), Value::from("func(x, t!)"));
+        assert_eq!(eval("str(str)"), Value::from("str"));
+    }
+
+    #[test]
+    fn pretty_prints() {
+        assert_eq!(eval("pretty([1, [2, 3]])"), Value::from("[1, [2, 3]]"));
+        assert_eq!(eval("pretty([1, [2, 3]], 8)"), Value::from("[\n  1,\n  [2, 3]\n]"));
+        let err = Interpreter::new().eval("pretty(1, -1)").unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: pretty needs a width of at least 0, but was given -1");
+    }
+
+    #[test]
+    fn strings_count_against_the_size_limit() {
+        let limits = VmLimits { max_collection_size: Some(10), ..VmLimits::default() };
+        let mut interpreter = Interpreter::with_limits(limits);
+        interpreter.eval("t = [1, 2, 3, 4, 5];").unwrap();
+        assert!(interpreter.eval("str(t)").is_err());
+        assert!(interpreter.eval("pretty(t)").is_err());
+        assert!(interpreter.eval("format(\"{}{}\", t, t)").is_err());
+        assert_eq!(interpreter.eval("str([1, 2])").unwrap(), Value::from("[1, 2]"));
+        assert_eq!(interpreter.eval("format(\"<{}>\", 12)").unwrap(), Value::from("<12>"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+use crate::code::code::is_jump;
+use crate::code::debug::lookup;
+use crate::vm::debug::{DebugHook, DebugState};
+
+/*
+ * Logs every instruction the VM runs, one line each, with tab-separated fields:
+ *
+ * function  offset  line  mnemonic  operand  stack size  top of stack
+ * fib       4       3     Const     1        4           int(1), int(5), fn([])
+ *
+ * The offset is where the instruction is in its function's bytes, and jump operands are byte
+ * offsets too, so both match the disassembly (including any `Line` markers). The line is `-`
+ * when the program was compiled without line info, as is the operand for instructions that
+ * don't take one. The stack is shown as it is after the instruction runs, with up to
+ * `STACK_PREVIEW` values listed from the top down.
+ */
+
+const STACK_PREVIEW: usize = 3;
+
+/** Limits which instructions are traced. Unset filters let everything through. */
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub function: Option<String>,
+    pub lines: Option<RangeInclusive<usize>>,
+}
+
+pub struct Tracer<W: Write> {
+    output: W,
+    filter: TraceFilter,
+    /** The fields of the instruction that's running, waiting on the stack it leaves behind */
+    pending: Option<String>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, filter: TraceFilter) -> Self {
+        Self {
+            output,
+            filter,
+            pending: None,
+        }
+    }
+}
+
+fn function_label(state: &DebugState) -> String {
+    match &state.code(state.depth()).debug_info().name {
+        Some(name) => name.clone(),
+        None if state.depth() == 0 => "<main>".to_owned(),
+        None => "<anonymous>".to_owned(),
+    }
+}
+
+impl<W: Write> DebugHook for Tracer<W> {
+    fn before_instruction(&mut self, state: &DebugState) {
+        let code = state.code(state.depth());
+        let ip = state.position(state.depth());
+        let function = function_label(state);
+        let line = code.line_at(ip);
+
+        if self.filter.function.as_ref().is_some_and(|name| *name != function) {
+            return;
+        }
+        if let Some(lines) = &self.filter.lines {
+            if !line.is_some_and(|line| lines.contains(&line)) {
+                return;
+            }
+        }
+
+        let word = state.word();
+        let (operand_counts, mnemonic) = lookup(word.op).unwrap_or((&[], "?"));
+        let operand = if operand_counts.is_empty() {
+            "-".to_owned()
+        } else if is_jump(word.op) {
+            code.byte_offset(word.arg as usize).to_string()
+        } else {
+            word.arg.to_string()
+        };
+        let line = line.map_or("-".to_owned(), |line| line.to_string());
+        self.pending = Some(format!("{}\t{}\t{}\t{}\t{}", function, code.byte_offset(ip), line, mnemonic, operand));
+    }
+
+    fn after_instruction(&mut self, state: &DebugState) {
+        let Some(fields) = self.pending.take() else {
+            return;
+        };
+        let stack = state.stack();
+        let top: Vec<String> = stack.iter().rev().take(STACK_PREVIEW).map(|value| format!("{:?}", value)).collect();
+        writeln!(self.output, "{}\t{}\t{}", fields, stack.len(), top.join(", ")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceFilter, Tracer};
+    use crate::compiler::compiler::Compiler;
+    use crate::parser::parser;
+    use crate::test_util::SharedOutput;
+    use crate::vm::vm::VM;
+
+    const PROGRAM: &str = "program :trace;
+inc = func(n) {
+    n + 1
+};
+x = if inc(1) == 2 ? [inc(2)] : 0;";
+
+    fn trace(filter: TraceFilter) -> String {
+        let mut compiler = Compiler::new();
+        compiler.set_line_info(true);
+        compiler.compile_program(parser::parse_from_program(PROGRAM).unwrap());
+        let output = SharedOutput::default();
+        let mut vm = VM::new(compiler.finish());
+        vm.set_debug_hook(Box::new(Tracer::new(output.clone(), filter)));
+        vm.run().unwrap();
+        output.text()
+    }
+
+    #[test]
+    fn traces_every_instruction() {
+        let expected = [
+            "<main>\t5\t2\tConst\t1\t1\tfn([])",
+            "<main>\t8\t2\tToFn\t0\t1\tfn([])",
+            "<main>\t11\t2\tSetGVar\t0\t1\tfn([])",
+            "<main>\t14\t2\tPop\t-\t0\t",
+            "<main>\t20\t5\tGetGVar\t0\t1\tfn([])",
+            "<main>\t23\t5\tConst\t0\t2\tint(1), fn([])",
+            "<main>\t26\t5\tCall\t1\t2\tint(1), fn([])",
+            "inc\t5\t3\tGetLVar0\t-\t3\tint(1), int(1), fn([])",
+            "inc\t6\t3\tAddConst\t0\t3\tint(2), int(1), fn([])",
+            "inc\t9\t3\tReturn\t-\t1\tint(2)",
+            "<main>\t29\t5\tConst\t2\t2\tint(2), int(2)",
+            "<main>\t32\t5\tEq\t-\t1\ttrue",
+            "<main>\t33\t5\tJumpNotTrue\t51\t0\t",
+            "<main>\t36\t5\tGetGVar\t0\t1\tfn([])",
+            "<main>\t39\t5\tConst\t2\t2\tint(2), fn([])",
+            "<main>\t42\t5\tCall\t1\t2\tint(2), fn([])",
+            "inc\t5\t3\tGetLVar0\t-\t3\tint(2), int(2), fn([])",
+            "inc\t6\t3\tAddConst\t0\t3\tint(3), int(2), fn([])",
+            "inc\t9\t3\tReturn\t-\t1\tint(3)",
+            "<main>\t45\t5\tToTuple\t1\t1\ttup([int(3)])",
+            "<main>\t48\t5\tJump\t54\t1\ttup([int(3)])",
+            "<main>\t54\t5\tSetGVar\t1\t1\ttup([int(3)])",
+            "<main>\t57\t5\tPop\t-\t0\t",
+        ];
+        assert_eq!(trace(TraceFilter::default()), expected.map(|line| format!("{line}\n")).concat());
+    }
+
+    #[test]
+    fn filters() {
+        let inc_only = trace(TraceFilter { function: Some("inc".to_owned()), lines: None });
+        assert_eq!(inc_only.lines().count(), 6);
+        assert!(inc_only.lines().all(|line| line.starts_with("inc\t")));
+
+        let line_two = trace(TraceFilter { function: None, lines: Some(2..=2) });
+        assert_eq!(line_two.lines().count(), 4);
+        assert!(line_two.lines().all(|line| line.split('\t').nth(2) == Some("2")));
+    }
+}
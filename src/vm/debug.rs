@@ -0,0 +1,93 @@
+use crate::code::words::{Code, Word};
+use crate::object::object::Object;
+
+use super::frame::Frame;
+
+/**
+ * Lets a tool watch a program as it runs. Once a hook is set on the VM, it's called around every
+ * instruction with a view of the frames, stack and globals, and the program carries on once it
+ * returns.
+ */
+pub trait DebugHook {
+    fn before_instruction(&mut self, state: &DebugState);
+
+    /** Called once the instruction has run, where the state is now positioned at the next one */
+    fn after_instruction(&mut self, _state: &DebugState) {}
+
+    /**
+     * Checked after `before_instruction`, so a hook can stop the program instead of letting it run
+     * the instruction. The run then ends with `RuntimeError::Stopped`.
+     */
+    fn stop_requested(&self) -> bool {
+        false
+    }
+}
+
+/** The VM as it's about to run an instruction. Frames are counted by depth, from the main program at 0. */
+pub struct DebugState<'a> {
+    frames: &'a [Frame],
+    ip: usize,
+    stack: &'a [Object],
+    globals: &'a [Object],
+}
+
+impl<'a> DebugState<'a> {
+    pub(super) fn new(frames: &'a [Frame], ip: usize, stack: &'a [Object], globals: &'a [Object]) -> Self {
+        Self { frames, ip, stack, globals }
+    }
+
+    /** Depth of the frame that's running */
+    pub fn depth(&self) -> usize {
+        self.frames.len() - 1
+    }
+
+    pub fn frame(&self, depth: usize) -> &Frame {
+        &self.frames[depth]
+    }
+
+    pub fn code(&self, depth: usize) -> &Code {
+        self.frames[depth].code()
+    }
+
+    /**
+     * Index of the word a frame is on: the one about to run for the current frame, or the call that
+     * it's waiting on for any other frame
+     */
+    pub fn position(&self, depth: usize) -> usize {
+        if depth == self.depth() {
+            self.ip
+        } else {
+            self.frames[depth].ip - 1
+        }
+    }
+
+    /** The instruction about to run */
+    pub fn word(&self) -> Word {
+        self.code(self.depth()).words()[self.ip]
+    }
+
+    pub fn line(&self, depth: usize) -> Option<usize> {
+        self.code(depth).line_at(self.position(depth))
+    }
+
+    pub fn stack(&self) -> &[Object] {
+        self.stack
+    }
+
+    pub fn globals(&self) -> &[Object] {
+        self.globals
+    }
+
+    /** A frame's params and locals, paired with their names */
+    pub fn locals(&self, depth: usize) -> Vec<(&str, &Object)> {
+        let frame = &self.frames[depth];
+        frame
+            .code()
+            .debug_info()
+            .locals
+            .iter()
+            .zip(&self.stack[frame.stack_ptr..])
+            .map(|(name, value)| (name.as_str(), value))
+            .collect()
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt::Display;
+
+use super::limits::LimitExceeded;
+
+/** Stops a program before it finishes, either from the VM or from a native function it called */
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    Limit(LimitExceeded),
+    /** Raised by a native function, such as when it's given args it can't work with */
+    Native(String),
+    /** A debug hook asked for the program to stop, such as when the debugger is told to quit */
+    Stopped,
+}
+
+impl RuntimeError {
+    pub fn native(message: impl Into<String>) -> Self {
+        RuntimeError::Native(message.into())
+    }
+}
+
+impl From<LimitExceeded> for RuntimeError {
+    fn from(err: LimitExceeded) -> Self {
+        RuntimeError::Limit(err)
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::Limit(err) => err.fmt(f),
+            RuntimeError::Native(message) => write!(f, "Runtime error: {}", message),
+            RuntimeError::Stopped => f.write_str("Program stopped"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -1,24 +1,25 @@
 use std::rc::Rc;
 
-use bytes::Bytes;
+use crate::code::words::Code;
 
 #[derive(Debug)]
 pub struct Frame {
-    ins: Rc<Bytes>,
-    pub ins_ptr: u64,
+    code: Rc<Code>,
+    /** Index of the next word to run */
+    pub ip: usize,
     pub stack_ptr: usize,
 }
 
 impl Frame {
-    pub fn new(ins: Rc<Bytes>, ins_ptr: u64, stack_ptr: usize) -> Self {
+    pub fn new(code: Rc<Code>, ip: usize, stack_ptr: usize) -> Self {
         Self {
-            ins,
-            ins_ptr,
+            code,
+            ip,
             stack_ptr,
         }
     }
 
-    pub fn instructions(&self) -> Rc<Bytes> {
-        self.ins.clone()
+    pub fn code(&self) -> &Rc<Code> {
+        &self.code
     }
 }
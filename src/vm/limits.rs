@@ -0,0 +1,74 @@
+use std::fmt::Display;
+
+/**
+ * Bounds on how much a program may do before the VM gives up on it. A limit of `None` leaves that
+ * resource unbounded. The defaults only bound the call depth, as the VM always has.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmLimits {
+    /** Instructions executed over the VM's lifetime */
+    pub max_instructions: Option<u64>,
+    /**
+     * Values on the stack, checked after every instruction and whenever a function is entered, since
+     * that's when room is made for all of its locals at once
+     */
+    pub max_stack: Option<usize>,
+    /** Nested function calls, not counting tail calls */
+    pub max_frames: Option<usize>,
+    /**
+     * Elements in any one tuple or set, or characters in a string built by `+` or `*`. This
+     * approximates a heap budget, and is checked before a range, index assignment or string
+     * operation allocates, so a huge value is refused rather than built.
+     */
+    pub max_collection_size: Option<usize>,
+}
+
+impl Default for VmLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: None,
+            max_stack: None,
+            max_frames: Some(2048),
+            max_collection_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Stack,
+    Frames,
+    CollectionSize,
+}
+
+/** Returned by the VM when a program tries to go past one of its limits */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: u64,
+}
+
+impl LimitExceeded {
+    /** Fails if `value` is over `max`, when there is a max */
+    pub fn check(limit: Limit, max: Option<usize>, value: usize) -> Result<(), LimitExceeded> {
+        match max {
+            Some(max) if value > max => Err(LimitExceeded { limit, max: max as u64 }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.limit {
+            Limit::Instructions => "instructions executed",
+            Limit::Stack => "stack size",
+            Limit::Frames => "call depth",
+            Limit::CollectionSize => "collection size",
+        };
+        write!(f, "Limit exceeded: {} is capped at {}", name, self.max)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
@@ -1,2 +1,8 @@
+pub mod debug;
+pub mod error;
 pub mod frame;
-pub mod vm;
\ No newline at end of file
+pub mod limits;
+pub mod native;
+pub mod sandbox;
+#[allow(clippy::module_inception)]
+pub mod vm;
@@ -0,0 +1,121 @@
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::object::object::Object;
+
+use super::error::RuntimeError;
+use super::sandbox::FileAccess;
+use super::vm::VM;
+
+pub type NativeFn = dyn Fn(&mut VmCtx, &[Object]) -> Result<Object, RuntimeError>;
+
+/**
+ * A function written in Rust that YSETL code calls like any other. It's bound to a global by
+ * registering it with the compiler (or the `Interpreter`) before the code using it is compiled.
+ */
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub min_args: usize,
+    /** `None` when it takes any number of args past the minimum */
+    pub max_args: Option<usize>,
+    func: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        min_args: usize,
+        max_args: Option<usize>,
+        func: impl Fn(&mut VmCtx, &[Object]) -> Result<Object, RuntimeError> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            min_args,
+            max_args,
+            func: Rc::new(func),
+        }
+    }
+
+    pub fn accepts(&self, arg_count: usize) -> bool {
+        arg_count >= self.min_args && self.max_args.is_none_or(|max| arg_count <= max)
+    }
+
+    /** Runs the function, once the number of args has been checked */
+    pub fn call(&self, ctx: &mut VmCtx, args: &[Object]) -> Result<Object, RuntimeError> {
+        if !self.accepts(args.len()) {
+            let expected = match self.max_args {
+                Some(max) if max == self.min_args => max.to_string(),
+                Some(max) => format!("{} to {}", self.min_args, max),
+                None => format!("at least {}", self.min_args),
+            };
+            return Err(RuntimeError::native(format!(
+                "{} takes {} args, but was given {}",
+                self.name,
+                expected,
+                args.len()
+            )));
+        }
+        (self.func)(ctx, args)
+    }
+}
+
+// Two natives are only the same function if they share the closure that was registered
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl Hash for NativeFunction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.func) as *const () as usize).hash(state);
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("native").field(&self.name).finish()
+    }
+}
+
+/** What a native function can do with the VM that called it */
+pub struct VmCtx<'a> {
+    vm: &'a mut VM,
+}
+
+impl<'a> VmCtx<'a> {
+    pub(super) fn new(vm: &'a mut VM) -> Self {
+        Self { vm }
+    }
+
+    /**
+     * Calls a YSETL or native function and runs it to completion, returning its result or the error
+     * it stopped with
+     */
+    pub fn call_value(&mut self, func: &Object, args: &[Object]) -> Result<Object, RuntimeError> {
+        self.vm.call_value(func, args)
+    }
+
+    /** Where the program's output goes, which is stdout unless the host has replaced it */
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.vm.output()
+    }
+
+    /** Where the program's input comes from, which is stdin unless the host has replaced it */
+    pub fn input(&mut self) -> &mut dyn BufRead {
+        self.vm.input()
+    }
+
+    /** The directories that the program's file builtins may use */
+    pub fn file_access(&self) -> &FileAccess {
+        self.vm.file_access()
+    }
+
+    /** Fails if a collection of this size would go past the VM's limit */
+    pub fn check_collection_size(&self, size: usize) -> Result<(), RuntimeError> {
+        Ok(self.vm.check_collection_size(size)?)
+    }
+}
@@ -0,0 +1,99 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/**
+ * The directories a program's file builtins may use, each allowing access to everything beneath
+ * it. Nothing is allowed until a host or the command line adds a directory, so an untrusted program
+ * can't touch the disk by default.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct FileAccess {
+    read_roots: Vec<PathBuf>,
+    write_roots: Vec<PathBuf>,
+}
+
+impl FileAccess {
+    /** Allows reading files and listing directories beneath a directory, which must exist */
+    pub fn allow_read(&mut self, root: impl AsRef<Path>) -> io::Result<()> {
+        self.read_roots.push(root.as_ref().canonicalize()?);
+        Ok(())
+    }
+
+    /** Allows creating and changing files beneath a directory, which must exist */
+    pub fn allow_write(&mut self, root: impl AsRef<Path>) -> io::Result<()> {
+        self.write_roots.push(root.as_ref().canonicalize()?);
+        Ok(())
+    }
+
+    /** Resolves a path that the program wants to read, if it's allowed to */
+    pub fn check_read(&self, path: &str) -> Result<PathBuf, String> {
+        check(&self.read_roots, path, "reading")
+    }
+
+    /** Resolves a path that the program wants to write, if it's allowed to */
+    pub fn check_write(&self, path: &str) -> Result<PathBuf, String> {
+        check(&self.write_roots, path, "writing")
+    }
+}
+
+/**
+ * Resolves a path to where it really is, following any links and `..`, and checks that it's beneath
+ * one of the roots. A file that doesn't exist yet is resolved through its parent directory, unless
+ * something is already there: a link that points nowhere would be followed when the file is
+ * created, to wherever it points.
+ */
+fn check(roots: &[PathBuf], path: &str, access: &str) -> Result<PathBuf, String> {
+    let requested = Path::new(path);
+    let resolved = match requested.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            let parent = requested.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            match (parent.canonicalize(), requested.file_name()) {
+                (Ok(parent), Some(name)) if parent.join(name).symlink_metadata().is_err() => parent.join(name),
+                (Ok(_), Some(_)) => return Err(format!("{} is a link that can't be followed", path)),
+                _ => return Err(format!("{}: {}", path, err)),
+            }
+        }
+    };
+    if roots.iter().any(|root| resolved.starts_with(root)) {
+        Ok(resolved)
+    } else {
+        Err(format!("{} is outside of the directories allowed for {}", path, access))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileAccess;
+    use std::fs;
+
+    #[test]
+    fn stays_beneath_roots() {
+        let root = std::env::temp_dir().join(format!("ysetl-sandbox-{}", std::process::id()));
+        let allowed = root.join("allowed");
+        fs::create_dir_all(allowed.join("sub")).unwrap();
+        let mut access = FileAccess::default();
+        access.allow_write(&allowed).unwrap();
+        let path = |rest: &str| format!("{}/{}", allowed.to_str().unwrap(), rest);
+
+        assert_eq!(access.check_write(&path("sub/../new.txt")), Ok(allowed.canonicalize().unwrap().join("new.txt")));
+        let outside = path("../new.txt");
+        assert_eq!(
+            access.check_write(&outside),
+            Err(format!("{} is outside of the directories allowed for writing", outside))
+        );
+        assert!(access.check_write(&path("missing/../../new.txt")).is_err());
+        assert!(access.check_write(&path("..")).is_err());
+
+        // A link that points nowhere yet would create its target outside of the root
+        #[cfg(unix)]
+        {
+            let dangling = path("escape.txt");
+            std::os::unix::fs::symlink(root.join("escaped.txt"), &dangling).unwrap();
+            assert_eq!(access.check_write(&dangling), Err(format!("{} is a link that can't be followed", dangling)));
+            assert!(!root.join("escaped.txt").exists());
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,17 +1,23 @@
-use bytes::{Buf};
-use std::io::Cursor;
+use std::io::{self, BufRead, BufReader, Write};
 use std::rc::Rc;
 
 use crate::code::code::{self, OpCode};
 use crate::code::debug::lookup;
+use crate::code::words::{Code, Word};
 use crate::compiler::compiler::Bytecode;
+use crate::object::collection::{set_index, set_path, update};
 use crate::object::math::{math_op, ObjectMath};
 use crate::object::object::{BaseObject, Object, ObjectOps};
+use crate::object::string;
 
+use super::debug::{DebugHook, DebugState};
+use super::error::RuntimeError;
 use super::frame::Frame;
+use super::limits::{Limit, LimitExceeded, VmLimits};
+use super::native::VmCtx;
+use super::sandbox::FileAccess;
 
 const STACK_SIZE: usize = 2048;
-const MAX_FRAMES: usize = 2048;
 
 trait Stack {
     /** Pops last two objects off the stack, and returns them in the order they're removed */
@@ -24,64 +30,273 @@ impl Stack for Vec<Object> {
     }
 }
 
-#[derive(Debug)]
+/** Number of elements in a tuple or set, or zero for anything else */
+fn collection_len(obj: &Object) -> usize {
+    match obj.heap() {
+        Some(BaseObject::Tuple(els)) => els.len(),
+        Some(BaseObject::Set(els)) => els.len(),
+        _ => 0,
+    }
+}
+
+/**
+ * Whether `collection` has `value` as an element, or as a substring for strings. Returns `None` if
+ * `collection` isn't something that can be searched.
+ */
+fn contains(collection: &Object, value: &Object) -> Option<bool> {
+    match (collection.heap()?, value.heap()) {
+        (BaseObject::String(str), Some(BaseObject::String(part))) => Some(str.contains(part.as_str())),
+        (BaseObject::String(_), _) => None,
+        (BaseObject::Tuple(els), _) => Some(els.contains(value)),
+        (BaseObject::Set(els), _) => Some(els.contains(value)),
+        _ => None,
+    }
+}
+
+/** Where a program stands after `VM::run_for` returns */
+#[derive(Debug, PartialEq)]
+pub enum RunStatus {
+    /** The program ran to the end, with the value of its last statement */
+    Finished(Option<Object>),
+    /** The instruction budget ran out, and the next call picks up where this one stopped */
+    Yielded,
+    /** The program went past one of the VM's limits, or a native function it called failed */
+    Error(RuntimeError),
+}
+
+/** Where a variable lives, either in the globals or in the current frame's stack window */
+enum VarSlot {
+    Global(usize),
+    Stack(usize),
+}
+
 pub struct VM {
     call_stack: Vec<Frame>,
     constants: Vec<Object>,
     globals: Vec<Object>,
     match_stack: Vec<Object>,
+    /** The value of the main program's last statement, kept once that statement is done */
+    result: Option<Object>,
+    instruction_count: u64,
+    limits: VmLimits,
+    debug_hook: Option<Box<dyn DebugHook>>,
+    /** Where natives write the program's output, and read its input from */
+    output: Box<dyn Write>,
+    input: Box<dyn BufRead>,
+    file_access: FileAccess,
 
     stack: Vec<Object>,
 }
 
 impl VM {
     pub fn new(bytecode: Bytecode) -> Self {
-        let main_frame = Frame::new(Rc::new(bytecode.instuctions), 0, 0);
+        Self::with_limits(bytecode, VmLimits::default())
+    }
+
+    pub fn with_limits(bytecode: Bytecode, limits: VmLimits) -> Self {
+        let main_frame = Frame::new(Rc::new(Code::new(bytecode.instuctions)), 0, 0);
         let constants = bytecode.constants.into_iter().map(|bo| bo.wrap()).collect();
         // Must be initialized so that insertions can happen in any order
-        let globals = (0..bytecode.global_count).into_iter().map(|_| BaseObject::Null.wrap()).collect();
+        let mut globals = vec![Object::Null; bytecode.global_count];
+        for (index, native) in bytecode.natives {
+            globals[index] = BaseObject::NativeFunction(native).wrap();
+        }
 
         VM {
             call_stack: Vec::from([main_frame]),
             constants,
             globals,
             match_stack: Vec::new(),
+            result: None,
+            instruction_count: 0,
+            limits,
+            debug_hook: None,
+            output: Box::new(io::stdout()),
+            input: Box::new(BufReader::new(io::stdin())),
+            file_access: FileAccess::default(),
 
             stack: Vec::with_capacity(STACK_SIZE),
         }
     }
 
+    /**
+     * Replaces the program with another chunk from the same compiler, which runs next with the globals
+     * and constants left by the earlier ones. Anything left over from a program that stopped part way
+     * is dropped.
+     */
+    pub fn load(&mut self, bytecode: Bytecode) {
+        self.call_stack = Vec::from([Frame::new(Rc::new(Code::new(bytecode.instuctions)), 0, 0)]);
+        self.constants.extend(bytecode.constants.into_iter().map(|bo| bo.wrap()));
+        if self.globals.len() < bytecode.global_count {
+            self.globals.resize(bytecode.global_count, Object::Null);
+        }
+        for (index, native) in bytecode.natives {
+            self.globals[index] = BaseObject::NativeFunction(native).wrap();
+        }
+        self.stack.clear();
+        self.match_stack.clear();
+        self.result = None;
+    }
+
+    pub fn global(&self, index: usize) -> Option<&Object> {
+        self.globals.get(index)
+    }
+
+    /** Sets a global variable, making room for it if it's one the program hasn't loaded yet */
+    pub fn set_global(&mut self, index: usize, value: Object) {
+        if index >= self.globals.len() {
+            self.globals.resize(index + 1, Object::Null);
+        }
+        self.globals[index] = value;
+    }
+
+    /**
+     * Calls a function with the given args from outside the program, once it's finished. Anything
+     * left over from a program that stopped part way is dropped first.
+     */
+    pub fn call(&mut self, func: &Object, args: &[Object]) -> Result<Object, RuntimeError> {
+        self.call_stack.truncate(1);
+        self.stack.clear();
+        self.match_stack.clear();
+        self.call_value(func, args)
+    }
+
+    /**
+     * Calls a function and runs it to completion, returning its result. This can be done from inside
+     * a native function, in which case the callee runs on top of the frame that called the native,
+     * so natives can take callbacks. If the callee fails, the frames and stack are put back the way
+     * they were before the call, and the error is returned.
+     */
+    pub fn call_value(&mut self, func: &Object, args: &[Object]) -> Result<Object, RuntimeError> {
+        if let Some(BaseObject::NativeFunction(native)) = func.heap() {
+            return native.call(&mut VmCtx::new(self), args);
+        }
+        let arg_count = u16::try_from(args.len()).map_err(|_| RuntimeError::native("Too many args to call a function with"))?;
+        let return_depth = self.call_stack.len();
+        let stack_len = self.stack.len();
+        self.stack.push(func.reference());
+        self.stack.extend(args.iter().map(Object::reference));
+
+        let result = self.enter_function(arg_count).map_err(RuntimeError::from).and_then(|(ins, base_pointer)| {
+            self.push_frame(Frame::new(ins, 0, base_pointer))?;
+            match self.execute(u64::MAX, return_depth)? {
+                RunStatus::Finished(Some(value)) => Ok(value),
+                status => unreachable!("Function call ended without returning: {:?}", status),
+            }
+        });
+        if result.is_err() {
+            self.call_stack.truncate(return_depth);
+            self.stack.truncate(stack_len);
+        }
+        result
+    }
+
+    /** Replaces stdout as where the program's output goes */
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /** Replaces stdin as where the program's input comes from */
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    /** Sets which directories the program's file builtins may use */
+    pub fn set_file_access(&mut self, access: FileAccess) {
+        self.file_access = access;
+    }
+
+    /** Sets a hook to be called before each instruction, replacing any previous one */
+    pub fn set_debug_hook(&mut self, hook: Box<dyn DebugHook>) {
+        self.debug_hook = Some(hook);
+    }
+
+    pub fn take_debug_hook(&mut self) -> Option<Box<dyn DebugHook>> {
+        self.debug_hook.take()
+    }
+
     pub fn peek_top(&self) -> Option<&Object> {
         self.stack.last()
     }
 
-    pub fn run(&mut self) -> Option<Object> {
-        // Duplicate Rc for the current instructions, used to keep reference for cursor during function calls
-        let mut cur_ins = self.cur_frame().instructions();
-        let mut c = Cursor::new(cur_ins.as_ref());
-        let mut last_pop: Option<Object> = None;
+    /** Number of instructions executed so far */
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /**
+     * Runs the program to completion, returning the value of its last statement. If it goes past one
+     * of the VM's limits or a native function fails, it stops with an error instead.
+     */
+    pub fn run(&mut self) -> Result<Option<Object>, RuntimeError> {
+        match self.run_for(u64::MAX) {
+            RunStatus::Finished(result) => Ok(result),
+            RunStatus::Error(err) => Err(err),
+            RunStatus::Yielded => unreachable!("Ran out of an unbounded instruction budget"),
+        }
+    }
+
+    /**
+     * Runs at most `budget` instructions. If the program isn't done by then, its position is kept
+     * in the VM and the next call resumes from there, so several VMs can take turns on one thread.
+     * Once it stops with an error, the program can't be resumed.
+     */
+    pub fn run_for(&mut self, budget: u64) -> RunStatus {
+        match self.execute(budget, 0) {
+            Ok(status) => status,
+            Err(err) => RunStatus::Error(err),
+        }
+    }
 
-        while c.has_remaining() {
-            let op = c.get_u8();
+    /**
+     * Runs the current frame, until the program ends or the frame at `return_depth` returns. In that
+     * case, the value it returned is popped off the stack and handed back.
+     */
+    fn execute(&mut self, budget: u64, return_depth: usize) -> Result<RunStatus, RuntimeError> {
+        let max_instructions = self.limits.max_instructions.unwrap_or(u64::MAX);
+        let max_stack = self.limits.max_stack.unwrap_or(usize::MAX);
+        // Both stopping points are folded into one, so the loop only checks a single count
+        let stop = max_instructions.min(self.instruction_count.saturating_add(budget));
+        // Duplicate Rc for the current code, so the words stay borrowed across function calls
+        let mut code = self.cur_frame().code().clone();
+        let mut ip = self.cur_frame().ip;
+
+        while let Some(&Word { op, arg }) = code.words().get(ip) {
+            if self.instruction_count >= stop {
+                self.call_stack.last_mut().unwrap().ip = ip;
+                if self.instruction_count >= max_instructions {
+                    return Err(LimitExceeded { limit: Limit::Instructions, max: max_instructions }.into());
+                }
+                return Ok(RunStatus::Yielded);
+            }
+            if let Some(hook) = self.debug_hook.as_mut() {
+                hook.before_instruction(&DebugState::new(&self.call_stack, ip, &self.stack, &self.globals));
+                if hook.stop_requested() {
+                    self.call_stack.last_mut().unwrap().ip = ip;
+                    return Err(RuntimeError::Stopped);
+                }
+            }
+            ip += 1;
+            self.instruction_count += 1;
             match op {
                 code::Const::VAL => {
-                    let ptr = c.get_u16();
-                    let const_obj = self.constants[ptr as usize].reference();
+                    let const_obj = self.constants[arg as usize].reference();
                     self.stack.push(const_obj);
                 }
-                code::Null::VAL => self.stack.push(BaseObject::Null.wrap()),
-                code::True::VAL => self.stack.push(BaseObject::True.wrap()),
-                code::False::VAL => self.stack.push(BaseObject::False.wrap()),
+                code::Null::VAL => self.stack.push(Object::Null),
+                code::True::VAL => self.stack.push(Object::True),
+                code::False::VAL => self.stack.push(Object::False),
 
                 code::Pop::VAL => {
-                    last_pop = self.stack.pop();
-                    if last_pop.is_none() {
-                        panic!("Called pop on empty stack");
+                    let value = self.stack.pop().unwrap_or_else(|| panic!("Called pop on empty stack"));
+                    // The main program ends with the `Pop` of its last statement, whose value is the result
+                    if self.call_stack.len() == 1 && ip == code.words().len() {
+                        self.result = Some(value);
                     }
                 }
 
                 code::SetGVar::VAL => {
-                    let ptr = c.get_u16() as usize;
+                    let ptr = arg as usize;
                     // To do this in a straighforward manner, we would pop the stack, insert a reference
                     // to the globals vector, and push it back onto the stack, so we just leave it in the
                     // stack and reference it from there using `last` instead.
@@ -90,13 +305,13 @@ impl VM {
                 }
 
                 code::GetGVar::VAL => {
-                    let ptr = c.get_u16() as usize;
+                    let ptr = arg as usize;
                     let global = self.globals.get(ptr).unwrap();
                     self.stack.push(global.reference());
                 }
 
                 code::SetLVar::VAL => {
-                    let offset = c.get_u16() as usize;
+                    let offset = arg as usize;
                     // To do this in a straighforward manner, we would pop the stack, insert a reference
                     // to the globals vector, and push it back onto the stack, so we just leave it in the
                     // stack and reference it from there using `last` instead.
@@ -106,47 +321,111 @@ impl VM {
                 }
 
                 code::GetLVar::VAL => {
-                    let offset = c.get_u16() as usize;
+                    let offset = arg as usize;
                     let stack_ptr = self.cur_frame().stack_ptr + offset;
                     self.stack.push(self.stack[stack_ptr].reference());
                 }
 
+                code::TakeLVar::VAL => {
+                    let stack_ptr = self.cur_frame().stack_ptr + arg as usize;
+                    let local = std::mem::replace(&mut self.stack[stack_ptr], Object::Null);
+                    self.stack.push(local);
+                }
+
+                code::GetLVar0::VAL | code::GetLVar1::VAL | code::GetLVar2::VAL | code::GetLVar3::VAL => {
+                    let offset = (op - code::GetLVar0::VAL) as usize;
+                    let stack_ptr = self.cur_frame().stack_ptr + offset;
+                    self.stack.push(self.stack[stack_ptr].reference());
+                }
+
+                code::IncLocal::VAL => {
+                    let offset = arg as usize;
+                    let stack_ptr = self.cur_frame().stack_ptr + offset;
+                    let local = &self.stack[stack_ptr];
+                    let result = math_op(local, &Object::Integer(1), code::Add::VAL).unwrap_or_else(|| {
+                        panic!("Could not perform Add on types {:?} and {:?}", local, Object::Integer(1))
+                    });
+                    self.stack[stack_ptr] = result.reference();
+                    self.stack.push(result);
+                }
+
                 code::ToTuple::VAL => {
-                    let size = c.get_u16() as usize;
+                    let size = arg as usize;
+                    self.check_collection_size(size)?;
                     let drain_start: usize = self.stack.len() - size;
-                    let elements: Vec<Object> = self.stack.drain(drain_start..).collect();
+                    let elements = self.stack.drain(drain_start..).collect();
                     self.stack.push(BaseObject::Tuple(elements).wrap());
                 }
 
                 code::ToSet::VAL => {
-                    let size = c.get_u16() as usize;
+                    let size = arg as usize;
+                    self.check_collection_size(size)?;
                     let drain_start: usize = self.stack.len() - size;
-                    let elements: Vec<Object> = self.stack.drain(drain_start..).collect();
+                    let elements = self.stack.drain(drain_start..).collect();
                     self.stack.push(BaseObject::Set(elements).wrap());
                 }
 
                 code::ToTupleRn::VAL => {
-                    let size = c.get_u16();
-                    let elements = self.calculate_range(size);
-                    self.stack.push(BaseObject::Tuple(elements).wrap());
+                    let size = arg as u16;
+                    let elements = self.calculate_range(size)?;
+                    self.stack.push(BaseObject::Tuple(elements.into()).wrap());
                 }
 
                 code::ToSetRn::VAL => {
-                    let size = c.get_u16();
-                    let elements = self.calculate_range(size);
-                    self.stack.push(BaseObject::Set(elements).wrap());
+                    let size = arg as u16;
+                    let elements = self.calculate_range(size)?;
+                    self.stack.push(BaseObject::Set(elements.into_iter().collect()).wrap());
+                }
+
+                code::Concat::VAL => {
+                    let parts = self.stack.split_off(self.stack.len() - arg as usize);
+                    let mut text = String::new();
+                    let mut len = 0;
+                    // Each part is measured before it's added, so the string never grows past the size limit
+                    for part in parts.iter() {
+                        let rendered;
+                        let part = match part.heap() {
+                            Some(BaseObject::String(str)) => {
+                                len += str.char_count();
+                                str.as_str()
+                            }
+                            _ => {
+                                rendered = part.to_string();
+                                len += rendered.chars().count();
+                                &rendered
+                            }
+                        };
+                        self.check_collection_size(len)?;
+                        text.push_str(part);
+                    }
+                    self.stack.push(BaseObject::String(text.into()).wrap());
                 }
 
                 code::Jump::VAL => {
-                    let ptr = c.get_u16();
-                    c.set_position(ptr as u64);
+                    ip = arg as usize;
                 }
 
                 code::JumpNotTrue::VAL => {
-                    let ptr = c.get_u16();
                     let top = self.stack.pop().unwrap();
                     if !top.truthy() {
-                        c.set_position(ptr as u64);
+                        ip = arg as usize;
+                    }
+                }
+
+                code::LtJumpNotTrue::VAL => {
+                    let (right, left) = self.stack.pop_two();
+                    let result = math_op(&left, &right, code::Lt::VAL).unwrap_or_else(|| {
+                        panic!("Could not perform Lt on types {:?} and {:?}", left, right)
+                    });
+                    if !result.truthy() {
+                        ip = arg as usize;
+                    }
+                }
+
+                code::JumpTrue::VAL => {
+                    let top = self.stack.pop().unwrap();
+                    if top.truthy() {
+                        ip = arg as usize;
                     }
                 }
 
@@ -160,10 +439,9 @@ impl VM {
                 }
 
                 code::JumpNotMatch::VAL => {
-                    let ptr = c.get_u16();
                     let top = self.stack.pop();
                     if top.as_ref() != self.match_stack.last() {
-                        c.set_position(ptr as u64);
+                        ip = arg as usize;
                     }
                 }
 
@@ -173,59 +451,104 @@ impl VM {
                     self.stack.push(target.get_index(&index))
                 }
 
-                code::Call::VAL => {
-                    let arg_count = c.get_u16();
-                    let arg_count_size = arg_count as usize;
-                    let fn_obj = self.stack.get(self.stack.len() - arg_count_size - 1).unwrap().reference();
-                    match &fn_obj.inner.as_ref() {
-                        &BaseObject::Function{
-                            ins,
-                            locals,
-                            req_params,
-                            opt_params,
-                            locked_values,
-                        } => {
-                            let total_passable_args = req_params + opt_params;
-                            if arg_count < *req_params {
-                                panic!("Did not provide enough arguments to function");
-                            }
-                            if arg_count > total_passable_args {
-                                panic!("Provided too many arguments to function");
-                            }
-                            let base_pointer = self.stack.len() - arg_count_size;
-
-                            // Any opt params not provided must now be set to null
-                            let unaccounted_opts_count = total_passable_args - arg_count;
-                            (0..unaccounted_opts_count).for_each(|_| self.stack.push(BaseObject::Null.wrap()));
-            
-                            // let locked_offset = total_passable_args as usize;
-                            for value in locked_values.iter() {
-                                self.stack.push(value.reference());
-                            }
+                code::SetIndex::VAL => {
+                    let value = self.stack.pop().unwrap();
+                    let (index, mut target) = self.stack.pop_two();
+                    self.check_index(&index)?;
+                    set_index(&mut target, &index, value);
+                    self.stack.push(target);
+                }
 
-                            // This could be inefficient, but Rust doesn't really let me have uninitialized elements of an array/vector
-                            // I would definitely need some unsafe code to be more efficient.
-                            // I choose Null as the placeholder since OM (ISETL's Null) is the default value of uninitialized variables in ISETL
-                            let mut local_placeholders = (0..*locals).into_iter().map(|_| BaseObject::Null.wrap()).collect();
-                            self.stack.append(&mut local_placeholders);
-                            let new_frame = Frame::new(ins.clone(), c.position(), base_pointer);
-                            cur_ins = ins.clone();
-                            c = Cursor::new(&cur_ins.as_ref());
-                            self.push_frame(new_frame);
-                        }
-                        other => panic!("Cannot call {:?}", other)
+                code::SetIndexPath::VAL => {
+                    let value = self.stack.pop().unwrap();
+                    let path = self.stack.split_off(self.stack.len() - arg as usize);
+                    let mut target = self.stack.pop().unwrap();
+                    for index in path.iter() {
+                        self.check_index(index)?;
                     }
+                    set_path(&mut target, &path, value);
+                    self.stack.push(target);
                 }
 
-                code::Return::VAL => {
+                code::SetIndexGVar::VAL | code::SetIndexLVar::VAL => {
+                    let slot = self.var_slot(arg, op == code::SetIndexGVar::VAL);
+                    let value = self.stack.pop().unwrap();
+                    let (index, original) = self.stack.pop_two();
+                    self.check_index(&index)?;
+                    let result = self.update_var(slot, original, |target| set_index(target, &index, value));
+                    self.stack.push(result);
+                }
+
+                code::WithGVar::VAL | code::WithLVar::VAL | code::LessGVar::VAL | code::LessLVar::VAL => {
+                    let slot = self.var_slot(arg, op == code::WithGVar::VAL || op == code::LessGVar::VAL);
+                    let update_op = match op {
+                        code::WithGVar::VAL | code::WithLVar::VAL => code::With::VAL,
+                        _ => code::Less::VAL,
+                    };
+                    let (value, original) = self.stack.pop_two();
+                    let result = self.update_var(slot, original, |target| {
+                        if !update(target, &value, update_op) {
+                            panic!("Could not perform {} on types {:?} and {:?}", lookup(update_op).unwrap().1, target, value);
+                        }
+                    });
+                    self.check_collection_size(collection_len(&result))?;
+                    self.stack.push(result);
+                }
+
+                // Natives run in place, so after a tail call, the Return that follows hands back the result
+                code::Call::VAL | code::TailCall::VAL if self.calls_native(arg as u16) => {
+                    // The native may call back into the VM, which needs to know where this frame is
+                    self.call_stack.last_mut().unwrap().ip = ip;
+                    self.call_native(arg as u16)?;
+                }
+
+                code::Call::VAL => {
+                    let arg_count = arg as u16;
+                    let (ins, base_pointer) = self.enter_function(arg_count)?;
+                    self.call_stack.last_mut().unwrap().ip = ip;
+                    self.push_frame(Frame::new(ins.clone(), 0, base_pointer))?;
+                    code = ins;
+                    ip = 0;
+                }
+
+                code::TailCall::VAL => {
+                    let arg_count = arg as u16;
+                    // The main program has no frame to give up, so it makes a regular call
+                    let window = if self.call_stack.len() > 1 {
+                        self.pop_frame().stack_ptr - 1
+                    } else {
+                        self.call_stack.last_mut().unwrap().ip = ip;
+                        self.stack.len() - arg_count as usize - 1
+                    };
+
+                    // The callee and its args replace the current function's stack window
+                    let mut callee = self.stack.split_off(self.stack.len() - arg_count as usize - 1);
+                    self.stack.truncate(window);
+                    self.stack.append(&mut callee);
+
+                    let (ins, base_pointer) = self.enter_function(arg_count)?;
+                    self.push_frame(Frame::new(ins.clone(), 0, base_pointer))?;
+                    code = ins;
+                    ip = 0;
+                }
+
+                code::Return::VAL | code::ReturnNull::VAL => {
                     let last_frame = self.pop_frame();
-                    cur_ins = self.cur_frame().instructions();
-                    c = Cursor::new(&cur_ins.as_ref());
-                    c.set_position(last_frame.ins_ptr);
-                    let return_value = self.stack.pop().unwrap();
+                    code = self.cur_frame().code().clone();
+                    ip = self.cur_frame().ip;
+                    let return_value = match op {
+                        code::ReturnNull::VAL => Object::Null,
+                        _ => self.stack.pop().unwrap(),
+                    };
                     self.stack.truncate(last_frame.stack_ptr);
                     self.stack.pop(); // Remove the function on the stack?
                     self.stack.push(return_value);
+                    if self.call_stack.len() == return_depth {
+                        if let Some(hook) = self.debug_hook.as_mut() {
+                            hook.after_instruction(&DebugState::new(&self.call_stack, ip, &self.stack, &self.globals));
+                        }
+                        return Ok(RunStatus::Finished(self.stack.pop()));
+                    }
                 }
 
                 code::Add::VAL
@@ -237,34 +560,66 @@ impl VM {
                 | code::Lt::VAL
                 | code::Lteq::VAL => {
                     let (right, left) = self.stack.pop_two();
-                    let result = math_op(&left.inner, &right.inner, op);
+                    if let Some(len) = string::result_len(&left, &right, op)? {
+                        self.check_collection_size(len)?;
+                    }
+                    let result = math_op(&left, &right, op);
                     // There's probably a better way to do all this
                     if result.is_none() {
-                        panic!("Could not perform {} on types {:?} and {:?}", lookup(op).unwrap().1, left.inner, right.inner)
+                        panic!("Could not perform {} on types {:?} and {:?}", lookup(op).unwrap().1, left, right)
                     }
-                    self.stack.push(result.unwrap().wrap());
+                    self.stack.push(result.unwrap());
+                }
+                code::AddConst::VAL => {
+                    let ptr = arg as usize;
+                    let left = self.stack.pop().unwrap();
+                    let right = &self.constants[ptr];
+                    if let Some(len) = string::result_len(&left, right, code::Add::VAL)? {
+                        self.check_collection_size(len)?;
+                    }
+                    let result = math_op(&left, right, code::Add::VAL).unwrap_or_else(|| {
+                        panic!("Could not perform Add on types {:?} and {:?}", left, right)
+                    });
+                    self.stack.push(result);
+                }
+
+                code::With::VAL | code::Less::VAL | code::Union::VAL => {
+                    let (right, mut left) = self.stack.pop_two();
+                    if !update(&mut left, &right, op) {
+                        panic!("Could not perform {} on types {:?} and {:?}", lookup(op).unwrap().1, left, right);
+                    }
+                    self.check_collection_size(collection_len(&left))?;
+                    self.stack.push(left);
+                }
+                code::In::VAL | code::Notin::VAL => {
+                    let (right, left) = self.stack.pop_two();
+                    let found = contains(&right, &left).unwrap_or_else(|| {
+                        panic!("Could not perform {} on types {:?} and {:?}", lookup(op).unwrap().1, left, right)
+                    });
+                    self.stack.push(Object::from_bool(found == (op == code::In::VAL)));
                 }
                 code::Eq::VAL => {
                     let (right, left) = self.stack.pop_two();
-                    let result = if left == right {
-                        BaseObject::True.wrap()
-                    } else {
-                        BaseObject::False.wrap()
-                    };
-                    self.stack.push(result);
+                    self.stack.push(Object::from_bool(left == right));
                 }
                 code::Neq::VAL => {
                     let (right, left) = self.stack.pop_two();
-                    self.stack.push(if left != right {
-                        BaseObject::True.wrap()
-                    } else {
-                        BaseObject::False.wrap()
-                    });
+                    self.stack.push(Object::from_bool(left != right));
                 }
 
+                code::Size::VAL => {
+                    let val = self.stack.pop().unwrap();
+                    let size = match val.heap() {
+                        Some(BaseObject::String(str)) => str.char_count(),
+                        Some(BaseObject::Tuple(els)) => els.len(),
+                        Some(BaseObject::Set(els)) => els.len(),
+                        _ => panic!("Cannot take the size of {:?}", val),
+                    };
+                    self.stack.push(Object::Integer(size as i64));
+                }
                 code::Negate::VAL => {
                     let val = self.stack.pop().unwrap();
-                    self.stack.push(val.inner.negate().unwrap().wrap());
+                    self.stack.push(val.negate().unwrap());
                 }
                 code::Not::VAL => {
                     let val = self.stack.pop().unwrap();
@@ -272,12 +627,12 @@ impl VM {
                 }
 
                 code::ToFn::VAL => {
-                    let locked_param_count = c.get_u16() as usize;
+                    let locked_param_count = arg as usize;
                     let fn_location = self.stack.len() - locked_param_count;
 
                     let locked_values = self.stack.drain(fn_location..).collect::<Vec<Object>>();
                     let func = self.stack.pop().unwrap();
-                    if let BaseObject::Function { ins, locals, req_params, opt_params, .. } = func.inner.as_ref() {
+                    if let Some(BaseObject::Function { ins, locals, req_params, opt_params, .. }) = func.heap() {
                         self.stack.push(BaseObject::Function {
                             ins: ins.clone(),
                             locals: *locals,
@@ -292,27 +647,153 @@ impl VM {
 
                 code => unimplemented!("Don't know how to execute code {code}"),
             }
+            // Calls check before making room for locals, and nothing else pushes more than one value,
+            // so this keeps the stack within the limit while a big literal is built without any call
+            if self.stack.len() > max_stack {
+                return Err(LimitExceeded { limit: Limit::Stack, max: max_stack as u64 }.into());
+            }
+            if let Some(hook) = self.debug_hook.as_mut() {
+                hook.after_instruction(&DebugState::new(&self.call_stack, ip, &self.stack, &self.globals));
+            }
+        }
+
+        self.call_stack.last_mut().unwrap().ip = ip;
+        Ok(RunStatus::Finished(self.result.as_ref().map(Object::reference)))
+    }
+
+    /**
+     * Prepares the stack for a call to the function sitting below the top `arg_count` values,
+     * returning the function's code and the base pointer of its new frame
+     */
+    fn enter_function(&mut self, arg_count: u16) -> Result<(Rc<Code>, usize), LimitExceeded> {
+        let arg_count_size = arg_count as usize;
+        let fn_obj = self.stack.get(self.stack.len() - arg_count_size - 1).unwrap().reference();
+        match fn_obj.heap() {
+            Some(BaseObject::Function {
+                ins,
+                locals,
+                req_params,
+                opt_params,
+                locked_values,
+            }) => {
+                let total_passable_args = req_params + opt_params;
+                if arg_count < *req_params {
+                    panic!("Did not provide enough arguments to function");
+                }
+                if arg_count > total_passable_args {
+                    panic!("Provided too many arguments to function");
+                }
+                let base_pointer = self.stack.len() - arg_count_size;
+
+                // Any opt params not provided must now be set to null
+                let unaccounted_opts_count = total_passable_args - arg_count;
+                (0..unaccounted_opts_count).for_each(|_| self.stack.push(Object::Null));
+
+                for value in locked_values.iter() {
+                    self.stack.push(value.reference());
+                }
+
+                // This could be inefficient, but Rust doesn't really let me have uninitialized elements of an array/vector
+                // I would definitely need some unsafe code to be more efficient.
+                // I choose Null as the placeholder since OM (ISETL's Null) is the default value of uninitialized variables in ISETL
+                let stack_size = self.stack.len() + *locals;
+                LimitExceeded::check(Limit::Stack, self.limits.max_stack, stack_size)?;
+                self.stack.resize(stack_size, Object::Null);
+                Ok((ins.clone(), base_pointer))
+            }
+            other => panic!("Cannot call {:?}", other)
+        }
+    }
+
+    /** Whether the function sitting below the top `arg_count` values is a native one */
+    fn calls_native(&self, arg_count: u16) -> bool {
+        let callee = &self.stack[self.stack.len() - arg_count as usize - 1];
+        matches!(callee.heap(), Some(BaseObject::NativeFunction(_)))
+    }
+
+    /** Calls a native function with the top `arg_count` values, replacing them and it with the result */
+    fn call_native(&mut self, arg_count: u16) -> Result<(), RuntimeError> {
+        let args = self.stack.split_off(self.stack.len() - arg_count as usize);
+        let callee = self.stack.pop().unwrap();
+        let Some(BaseObject::NativeFunction(native)) = callee.heap() else {
+            unreachable!("Expected a native function, received: {:?}", callee);
+        };
+        let result = native.call(&mut VmCtx::new(self), &args)?;
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /** Finds where the variable named by an opcode's operand is stored */
+    fn var_slot(&self, arg: u32, global: bool) -> VarSlot {
+        let index = arg as usize;
+        if global {
+            VarSlot::Global(index)
+        } else {
+            VarSlot::Stack(self.cur_frame().stack_ptr + index)
         }
+    }
 
-        last_pop.map(|lp| lp.reference())
+    /**
+     * Applies an update to the collection in a variable, given the copy of it that was pushed before
+     * the update's operands were evaluated. That copy is dropped first, so the variable may end up
+     * holding the only reference, in which case the collection is changed in place.
+     */
+    fn update_var(&mut self, slot: VarSlot, original: Object, apply: impl FnOnce(&mut Object)) -> Object {
+        let var = match slot {
+            VarSlot::Global(index) => &mut self.globals[index],
+            VarSlot::Stack(index) => &mut self.stack[index],
+        };
+        // If evaluating the operands reassigned the variable, the update applies to its old value
+        let mut target = if original.same_ref(var) {
+            drop(original);
+            std::mem::replace(var, Object::Null)
+        } else {
+            original
+        };
+        apply(&mut target);
+        *var = target.reference();
+        target
     }
 
     fn cur_frame(&self) -> &Frame {
         self.call_stack.last().expect("No frames found, this shouldn't be possible")
     }
 
-    fn push_frame(&mut self, f: Frame) {
-        if self.call_stack.len() >= MAX_FRAMES {
-            panic!("Stack overflow!");
-        }
+    fn push_frame(&mut self, f: Frame) -> Result<(), LimitExceeded> {
+        LimitExceeded::check(Limit::Frames, self.limits.max_frames, self.call_stack.len() + 1)?;
         self.call_stack.push(f);
+        Ok(())
+    }
+
+    pub(super) fn output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+
+    pub(super) fn input(&mut self) -> &mut dyn BufRead {
+        self.input.as_mut()
+    }
+
+    pub(super) fn file_access(&self) -> &FileAccess {
+        &self.file_access
+    }
+
+    pub(super) fn check_collection_size(&self, size: usize) -> Result<(), LimitExceeded> {
+        LimitExceeded::check(Limit::CollectionSize, self.limits.max_collection_size, size)
+    }
+
+    /** Assigning to an index can grow a tuple to fit it, so the index must be within the size limit */
+    fn check_index(&self, index: &Object) -> Result<(), LimitExceeded> {
+        match index {
+            &Object::Integer(i) if i >= 0 => self.check_collection_size((i as usize).saturating_add(1)),
+            _ => Ok(()),
+        }
     }
 
     fn pop_frame(&mut self) -> Frame {
         self.call_stack.pop().expect("Cannot pop empty callstack")
     }
 
-    fn calculate_range(&mut self, size: u16) -> Vec<Object> {
+    fn calculate_range(&mut self, size: u16) -> Result<Vec<Object>, LimitExceeded> {
         let start = self.stack.pop().unwrap();
         let end = self.stack.pop().unwrap();
         let step_opt = match size {
@@ -321,11 +802,9 @@ impl VM {
             3 => Some(self.stack.pop().unwrap()),
             _ => unreachable!(),
         };
-        if let (&BaseObject::Integer(start), &BaseObject::Integer(end)) =
-            (start.inner.as_ref(), end.inner.as_ref())
-        {
+        if let (Object::Integer(start), Object::Integer(end)) = (start, end) {
             let step = step_opt.map_or(1, |v| {
-                if let &BaseObject::Integer(v) = v.inner.as_ref() {
+                if let Object::Integer(v) = v {
                     v - start
                 } else {
                     panic!("Range elements must evaluate to integers");
@@ -336,8 +815,10 @@ impl VM {
             // to an empty range. Initially, I would have prefered this to fail, but I can see it
             // being handy to check if a range is valid if the resulting collection is truthy.
             if step == 0 || (step > 0 && start > end) || (step < 0 && start < end) {
-                return vec![];
+                return Ok(vec![]);
             }
+            let count = (end as i128 - start as i128) / step as i128 + 1;
+            self.check_collection_size(usize::try_from(count).unwrap_or(usize::MAX))?;
 
             let mut values: Vec<Object> = Vec::new();
             let mut x = start;
@@ -345,11 +826,11 @@ impl VM {
                 if (step > 0 && x > end) || (step < 0 && x < end) {
                     break;
                 }
-                values.push(BaseObject::Integer(x).wrap());
+                values.push(Object::Integer(x));
                 x += step;
             }
 
-            values
+            Ok(values)
         } else {
             panic!("Range elements must evaluate to integers");
         }
@@ -358,9 +839,14 @@ impl VM {
 
 #[cfg(test)]
 mod tests {
-    use super::VM;
+    use super::{RunStatus, VM};
+    use crate::vm::error::RuntimeError;
+    use crate::vm::limits::{Limit, LimitExceeded, VmLimits};
+    use crate::vm::native::NativeFunction;
+    use crate::code::assembler::assemble;
     use crate::compiler::compiler::Compiler;
     use crate::object::object::BaseObject::{self, *};
+    use crate::object::object::Object;
     use crate::parser::parser;
 
     fn vm_from(input: &str) -> VM {
@@ -369,9 +855,53 @@ mod tests {
         VM::new(c.finish())
     }
 
+    fn test_asm(source: &str, result: BaseObject) {
+        let mut vm = VM::new(assemble(source).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.peek_top(), Some(&result.wrap()), "For assembly: {}", source);
+    }
+
+    /** A function that calls itself `depth` times before returning how deep it went */
+    fn recursion_asm(depth: usize) -> std::string::String {
+        format!(
+            r#"
+            .const 0 int 0
+            .const 1 int 1
+            .const 2 func req=1 opt=0 locals=0
+            .const 3 int {depth}
+            .global 0 f
+
+            .code main
+                Const 2
+                SetGVar 0
+                Pop
+                GetGVar 0
+                Const 3
+                Call 1
+
+            .code 2
+                GetLVar 0
+                Const 0
+                Eq
+                JumpNotTrue recurse
+                Const 0
+                Return
+            recurse:
+                GetGVar 0
+                GetLVar 0
+                Const 1
+                Subtract
+                Call 1
+                Const 1
+                Add
+                Return
+            "#
+        )
+    }
+
     fn test_input(input: &str, result: BaseObject) {
         let mut vm = vm_from(input);
-        vm.run();
+        vm.run().unwrap();
         assert!(
             vm.peek_top() == Some(&result.wrap()),
             "For input: {}",
@@ -429,4 +959,447 @@ mod tests {
         test_input("if (1 >= 5) ? 1 + 1 : 2 * 2", Integer(4));
         test_input("if (1 < 5) ? 1 + 1 : 2 * 2", Integer(2));
     }
+
+    #[test]
+    fn empty_blocks() {
+        test_input("case { true : { } }", Null);
+        test_input("case { false : 1, ~ : { } }", Null);
+    }
+
+    #[test]
+    fn optimized_matches_unoptimized() {
+        let inputs = [
+            "if !(1 < 2) ? 1 : 2",
+            "case (3) { 1 : 10, 3 : { 30; 31 }, ~ : 99 }",
+            "case { false : 1, !false : 2 }",
+            "((a) => if !a ? 1 : 2)(true)",
+        ];
+        for input in inputs {
+            let mut optimized = Compiler::with_optimizations(true);
+            optimized.compile_expr(parser::parse_from_expr(input).unwrap());
+            let mut optimized_vm = VM::new(optimized.finish());
+            optimized_vm.run().unwrap();
+
+            let mut vm = vm_from(input);
+            vm.run().unwrap();
+            assert_eq!(optimized_vm.peek_top(), vm.peek_top(), "For input: {}", input);
+        }
+
+        // A program's result is the value of its last statement, even one that has no effect
+        let programs = ["x = 1; 5;", "1.0 == 2.0;", "x = [1]; x;", "f = func(a) { a + 1 }; f(2);"];
+        for program in programs {
+            let source = format!("program :any; {}", program);
+            let results = [false, true].map(|optimize| {
+                let mut compiler = Compiler::with_optimizations(optimize);
+                compiler.compile_program(parser::parse_from_program(&source).unwrap());
+                VM::new(compiler.finish()).run().unwrap()
+            });
+            assert_eq!(results[0], results[1], "For program: {}", program);
+            assert!(results[0].is_some(), "For program: {}", program);
+        }
+    }
+
+    #[test]
+    fn optimized_rejects_non_boolean_not() {
+        for optimize in [false, true] {
+            let mut compiler = Compiler::with_optimizations(optimize);
+            compiler.compile_expr(parser::parse_from_expr("if !1 ? 2 : 3").unwrap());
+            let mut vm = VM::new(compiler.finish());
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.run()));
+            assert!(result.is_err(), "!1 was allowed with optimizations set to {}", optimize);
+        }
+    }
+
+    #[test]
+    fn backward_jumps() {
+        test_asm(
+            r#"
+            .const 0 int 0
+            .const 1 int 1
+            .const 2 int 5
+            .global 0 i
+
+            .code main
+                Const 2
+                SetGVar 0
+                Pop
+            loop:
+                Const 0
+                GetGVar 0
+                Lt
+                JumpNotTrue done
+                GetGVar 0
+                Const 1
+                Subtract
+                SetGVar 0
+                Pop
+                Jump loop
+            done:
+                GetGVar 0
+            "#,
+            Integer(0),
+        );
+    }
+
+    #[test]
+    fn jump_to_end() {
+        test_asm(
+            r#"
+            .const 0 int 1
+            .const 1 int 2
+
+            .code main
+                Const 0
+                Jump end
+                Const 1
+            end:
+            "#,
+            Integer(1),
+        );
+    }
+
+    #[test]
+    fn wide_constants() {
+        test_asm(
+            r#"
+            .const 0 int 1
+            .const 1 int 2
+
+            .code main
+                Const32 1
+            "#,
+            Integer(2),
+        );
+    }
+
+    #[test]
+    fn wide_jumps() {
+        // Each branch holds ~90KB of bytecode, so both jumps need a u32 target
+        let table: Vec<std::string::String> = (0..30_000).map(|i| i.to_string()).collect();
+        let table = table.join(",");
+        test_input(&format!("if false ? [{table}] : (if true ? 7 : [{table}])"), Integer(7));
+    }
+
+    #[test]
+    fn deep_frames() {
+        test_asm(&recursion_asm(2000), Integer(2000));
+    }
+
+    #[test]
+    fn too_many_frames() {
+        let result = VM::new(assemble(&recursion_asm(5000)).unwrap()).run();
+        assert_eq!(result, Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Frames, max: 2048 })));
+    }
+
+    fn run_limited(input: &str, limits: VmLimits) -> Result<Option<Object>, RuntimeError> {
+        let mut c = Compiler::new();
+        c.compile_program(parser::parse_from_program(&format!("program :any; {}", input)).unwrap());
+        VM::with_limits(c.finish(), limits).run()
+    }
+
+    #[test]
+    fn limits() {
+        let looping = "count = 0; count = func(n) { if n == 1000 ? n : count(n + 1) }; count(0);";
+        let limits = VmLimits { max_instructions: Some(100), ..VmLimits::default() };
+        assert_eq!(run_limited(looping, limits), Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Instructions, max: 100 })));
+        let limits = VmLimits { max_instructions: Some(100_000), ..VmLimits::default() };
+        assert_eq!(run_limited(looping, limits), Ok(Some(Integer(1000).wrap())));
+
+        // Each call holds its argument and two locals
+        let recursive = "f = 0; f = func(n) { a = n; b = a; if n == 0 ? 0 : 1 + f(n - 1) }; f(100);";
+        let limits = VmLimits { max_stack: Some(200), ..VmLimits::default() };
+        assert_eq!(run_limited(recursive, limits), Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Stack, max: 200 })));
+        // The main program can fill the stack too, without calling anything
+        let literal = format!("[{}];", (0..300).map(|i| i.to_string()).collect::<Vec<_>>().join(", "));
+        assert_eq!(run_limited(&literal, limits), Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Stack, max: 200 })));
+        let parts = format!("x = 1; \"{}\";", "${x}".repeat(250));
+        assert_eq!(run_limited(&parts, limits), Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Stack, max: 200 })));
+        assert!(run_limited("[1, 2, 3];", limits).is_ok());
+        let limits = VmLimits { max_frames: Some(50), ..VmLimits::default() };
+        assert_eq!(run_limited(recursive, limits), Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Frames, max: 50 })));
+
+        // The range is refused before any of it is built
+        let limits = VmLimits { max_collection_size: Some(1000), ..VmLimits::default() };
+        let size_error = Err(RuntimeError::Limit(LimitExceeded { limit: Limit::CollectionSize, max: 1000 }));
+        assert_eq!(run_limited("[1..1000000000000];", limits), size_error);
+        assert_eq!(run_limited("t = []; t[5000] = 1;", limits), size_error);
+        let grow = "grow = 0; grow = func(n, s) { if n == 0 ? s : grow(n - 1, s with n) };";
+        assert_eq!(run_limited(&format!("{grow} grow(2000, {{}});"), limits), size_error);
+        assert!(run_limited(&format!("{grow} grow(1000, {{}}) union {{1..1000}};"), limits).is_ok());
+    }
+
+    fn run_program(input: &str) -> Option<Object> {
+        let wrapped_input = format!("program :any; {}", input);
+        let mut c = Compiler::new();
+        c.compile_program(parser::parse_from_program(&wrapped_input).unwrap());
+        VM::new(c.finish()).run().unwrap()
+    }
+
+    #[test]
+    fn tail_calls() {
+        // Both of these recurse far deeper than the frame limit would allow for regular calls
+        let sum = run_program(
+            "sum = 0; sum = func(n, acc) { if n == 0 ? acc : sum(n - 1, acc + n) }; sum(100000, 0);",
+        );
+        assert_eq!(sum, Some(Integer(5_000_050_000).wrap()));
+
+        let is_even = run_program(
+            "is_even = 0; is_odd = 0;
+            is_even = func(n) { case { n == 0 : true, ~ : is_odd(n - 1) } };
+            is_odd = func(n) { case { n == 0 : false, ~ : is_even(n - 1) } };
+            is_even(10001);",
+        );
+        assert_eq!(is_even, Some(False.wrap()));
+    }
+
+    #[test]
+    fn run_in_slices() {
+        let source = "program :any; fib = 0; fib = func(n) { if n < 2 ? n : fib(n - 1) + fib(n - 2) }; fib(15);";
+        let compile = || {
+            let mut c = Compiler::new();
+            c.compile_program(parser::parse_from_program(source).unwrap());
+            VM::new(c.finish())
+        };
+        let mut whole = compile();
+        let expected = whole.run().unwrap();
+
+        // Two programs take turns, in slices small enough to stop in the middle of calls
+        let mut vms = [compile(), compile()];
+        let mut yields = 0;
+        let mut results = [None, None];
+        while results.iter().any(Option::is_none) {
+            for (vm, result) in vms.iter_mut().zip(results.iter_mut()).filter(|(_, r)| r.is_none()) {
+                match vm.run_for(7) {
+                    RunStatus::Finished(value) => *result = Some(value),
+                    RunStatus::Yielded => yields += 1,
+                    RunStatus::Error(err) => panic!("{}", err),
+                }
+            }
+        }
+        assert_eq!(results, [Some(expected.clone()), Some(expected.clone())]);
+        assert_eq!(vms[0].instruction_count(), whole.instruction_count());
+        assert!(yields as u64 >= 2 * (whole.instruction_count() / 7));
+
+        // A finished program stays finished
+        assert_eq!(vms[0].run_for(7), RunStatus::Finished(expected));
+
+        let limits = VmLimits { max_instructions: Some(10), ..VmLimits::default() };
+        let mut c = Compiler::new();
+        c.compile_program(parser::parse_from_program(source).unwrap());
+        let mut limited = VM::with_limits(c.finish(), limits);
+        assert_eq!(limited.run_for(7), RunStatus::Yielded);
+        assert_eq!(limited.run_for(7), RunStatus::Error(RuntimeError::Limit(LimitExceeded { limit: Limit::Instructions, max: 10 })));
+    }
+
+    #[test]
+    fn collection_updates() {
+        let set = run_program(
+            "build = 0; build = func(n, s) { if n == 0 ? s : build(n - 1, s with n less (n + 1)) };
+            build(20000, {});",
+        );
+        assert_eq!(set, Some(BaseObject::Set([Integer(1).wrap()].into_iter().collect()).wrap()));
+
+        let tuples = run_program("t = [1, 2, 3]; u = t; u[0] = 10; u[4] = 5; [t, u];");
+        assert_eq!(
+            format!("{:?}", tuples.unwrap()),
+            "tup([tup([int(1), int(2), int(3)]), tup([int(10), int(2), int(3), null, int(5)])])"
+        );
+
+        test_input("{1, 2} union {2, 3} == {3, 2, 1}", True);
+        test_input("[1] with 2 == [1, 2]", True);
+    }
+
+    #[test]
+    fn self_updates() {
+        let shared = run_program("s = {1}; t = s; s = s with 2; s = s less 1; [s, t] == [{2}, {1}];");
+        assert_eq!(shared, Some(True.wrap()));
+
+        let indexed = run_program("t = [1, 2]; u = t; t[0] = 5; [t, u] == [[5, 2], [1, 2]];");
+        assert_eq!(indexed, Some(True.wrap()));
+
+        let local = run_program("f = func(t) { t[1] = 0; t = t with 3; t }; x = [1, 2]; [f(x), x] == [[1, 0, 3], [1, 2]];");
+        assert_eq!(local, Some(True.wrap()));
+
+        // Reassigning the variable while computing the new element doesn't affect what's updated
+        let reassigned = run_program("t = [1]; t = t with (t = [7]); t == [1, [7]];");
+        assert_eq!(reassigned, Some(True.wrap()));
+    }
+
+    #[test]
+    fn nested_index_assignment() {
+        let nested = run_program("t = [[1, 2], [3]]; u = t; t[0][1] = 5; t[1][1] = [6]; t[1][1][0] = 7; [t, u];");
+        assert_eq!(nested.unwrap().to_string(), "[[[1, 5], [3, [7]]], [[1, 2], [3]]]");
+
+        let local = run_program("f = func(t, i) { t[i][i] = i; t }; f([[0], [0, 0]], 1);");
+        assert_eq!(local.unwrap().to_string(), "[[0], [0, 1]]");
+
+        // Other selectors can't be assigned to, and the compiler says which one it was given
+        let range = std::panic::catch_unwind(|| run_program("t = [[1]]; t[0][0..1] = [2];")).unwrap_err();
+        assert_eq!(range.downcast_ref::<std::string::String>().unwrap(), "Can't assign to a range of 't', only to its indexes");
+    }
+
+    #[test]
+    fn specialized_ops() {
+        // IncLocal, GetLVar0..3, AddConst, LtJumpNotTrue and ReturnNull
+        let result = run_program(
+            "f = func(a, b, c, d, e) {
+                e = e + 1;
+                e = e + 1;
+                if a < b ? a + b + c + d + e + 10 : 0
+            };
+            g = func() {};
+            [f(1, 2, 3, 4, 5), f(2, 1, 0, 0, 0), g()];",
+        );
+        assert_eq!(format!("{:?}", result.unwrap()), "tup([int(27), int(0), null])");
+    }
+
+    #[test]
+    fn string_literals() {
+        let string = |input: &str| match run_program(input) {
+            Some(Object::Heap(inner)) => match inner.as_ref() {
+                BaseObject::String(str) => str.to_string(),
+                other => panic!("Expected a string, got {:?}", other),
+            },
+            other => panic!("Expected a string, got {:?}", other),
+        };
+        assert_eq!(string(r#""a\nb\t\"c\"\\ \$\u{1F600}";"#), "a\nb\t\"c\"\\ $\u{1F600}");
+        assert_eq!(string(r#"r"C:\dir\${x}";"#), r"C:\dir\${x}");
+        assert_eq!(string("\"\"\"\n  say \"hi\"\n  ${1 + 1}\"\"\";"), "  say \"hi\"\n  2");
+
+        // Strings are inserted as they are, and anything else as it would be written in source
+        assert_eq!(string(r#"x = 2; name = "y"; "${name} = ${x * 3}, ${[name, {x}]}!";"#), r#"y = 6, ["y", {2}]!"#);
+        assert_eq!(string(r#""${"nested ${1}"}${null}";"#), "nested 1null");
+        assert!(parser::parse_from_program(r#"program :any; "\u{D800}";"#).is_err());
+    }
+
+    #[test]
+    fn string_ops() {
+        let result = run_program(
+            r#"s = "añ" + "b" * 2;
+            [s, 3 * "-", #s, s[1], s[3], "abc" < "abd", "b" <= "ab", "ñb" in s, "x" notin s, #""];"#,
+        );
+        assert_eq!(result.unwrap().to_string(), r#"["añbb", "---", 4, "ñ", "b", true, false, true, true, 0]"#);
+        test_input("[#[1, 2], #{3}, 2 in [1, 2], 2 notin {1, 2}]", Tuple([Integer(2).wrap(), Integer(1).wrap(), True.wrap(), False.wrap()].into_iter().collect()));
+
+        // A string built by + or * counts against the size limit like a collection does
+        let limits = VmLimits { max_collection_size: Some(10), ..VmLimits::default() };
+        let size_error = Err(RuntimeError::Limit(LimitExceeded { limit: Limit::CollectionSize, max: 10 }));
+        assert_eq!(run_limited(r#""ab" * 6;"#, limits), size_error);
+        assert_eq!(run_limited(r#"s = "abcdef"; s + s;"#, limits), size_error);
+        assert!(run_limited(r#""ab" * 5;"#, limits).is_ok());
+        assert_eq!(run_limited(r#"s = "a" * 8; "${s}${s}${s}${s}";"#, limits), size_error);
+        assert_eq!(run_limited(r#"s = [1, 2, 3, 4]; "${s}";"#, limits), size_error);
+        assert!(run_limited(r#"s = "a" * 5; "${s}${s}";"#, limits).is_ok());
+
+        // Even without a limit, a string too long to exist is an error rather than a crash
+        assert_eq!(
+            run_limited(r#""ab" * 9223372036854775807;"#, VmLimits::default()),
+            Err(RuntimeError::native("A string of 2 characters repeated 9223372036854775807 times is too long"))
+        );
+    }
+
+    fn run_with_natives(input: &str, limits: VmLimits) -> Result<Option<Object>, RuntimeError> {
+        let mut c = Compiler::new();
+        c.register_native(NativeFunction::new("sum", 0, None, |_, args| {
+            let total = args.iter().map(|arg| match arg {
+                Object::Integer(val) => Ok(*val),
+                other => Err(RuntimeError::native(format!("sum can't add {:?}", other))),
+            });
+            Ok(Integer(total.sum::<Result<i64, RuntimeError>>()?).wrap())
+        }));
+        c.register_native(NativeFunction::new("repeat", 2, Some(2), |ctx, args| {
+            let (Object::Integer(count), value) = (&args[0], &args[1]) else {
+                return Err(RuntimeError::native("repeat needs a count"));
+            };
+            ctx.check_collection_size(*count as usize)?;
+            Ok(Tuple((0..*count).map(|_| value.reference()).collect()).wrap())
+        }));
+        c.register_native(NativeFunction::new("map", 2, Some(2), |ctx, args| {
+            let Some(Tuple(els)) = args[1].heap() else {
+                return Err(RuntimeError::native("map needs a tuple"));
+            };
+            let mapped = els.iter().map(|el| ctx.call_value(&args[0], &[el.reference()]));
+            Ok(Tuple(mapped.collect::<Result<_, RuntimeError>>()?).wrap())
+        }));
+        // Where a boxed value lives, to tell whether an update copied it
+        c.register_native(NativeFunction::new("address", 1, Some(1), |_, args| match &args[0] {
+            Object::Heap(inner) => Ok(Integer(std::rc::Rc::as_ptr(inner) as i64).wrap()),
+            _ => Ok(Object::Null),
+        }));
+        // Calls a function, or gives null if it fails
+        c.register_native(NativeFunction::new("try", 1, Some(1), |ctx, args| {
+            Ok(ctx.call_value(&args[0], &[]).unwrap_or(Object::Null))
+        }));
+        c.compile_program(parser::parse_from_program(&format!("program :any; {}", input)).unwrap());
+        VM::with_limits(c.finish(), limits).run()
+    }
+
+    #[test]
+    fn natives() {
+        let run = |input| run_with_natives(input, VmLimits::default());
+        assert_eq!(run("sum(1, 2, 3) + sum();"), Ok(Some(Integer(6).wrap())));
+        assert_eq!(
+            format!("{:?}", run("repeat(2, \"x\"); add = sum; f = func(n) { add(n, 1) }; [f(1), repeat(2, f(0))];").unwrap()),
+            "Some(tup([int(2), tup([int(1), int(1)])]))"
+        );
+
+        assert_eq!(run("sum(1, 2.5);"), Err(RuntimeError::native("sum can't add float(2.5)")));
+        assert_eq!(run("repeat(1);"), Err(RuntimeError::native("repeat takes 2 args, but was given 1")));
+        let limits = VmLimits { max_collection_size: Some(10), ..VmLimits::default() };
+        assert_eq!(
+            run_with_natives("repeat(11, 0);", limits),
+            Err(RuntimeError::Limit(LimitExceeded { limit: Limit::CollectionSize, max: 10 }))
+        );
+    }
+
+    #[test]
+    fn updates_in_place() {
+        let run = |input| run_with_natives(input, VmLimits::default()).unwrap();
+        // Popping the variable's value as a statement doesn't leave it shared
+        let set = run("s = {1}; a = address(s); s; s = s with 2; s = s less 1; [address(s) == a, s];");
+        assert_eq!(set.unwrap().to_string(), "[true, {2}]");
+        let tuple = run("t = [1, 2]; a = address(t); t; t[0] = 5; t[3] = 7; [address(t) == a, t];");
+        assert_eq!(tuple.unwrap().to_string(), "[true, [5, 2, null, 7]]");
+        let local = run("f = func(t) { a = address(t); t[0] = 0; t = t with 3; [address(t) == a, t] }; f([1, 2]);");
+        assert_eq!(local.unwrap().to_string(), "[true, [0, 2, 3]]");
+
+        // A tail call passes its frame's collection on without copying it
+        let build = run(
+            "build = 0; build = func(n, at, s) {
+                if (if at == null ? false : address(s) != at) ? false : if n == 0 ? s : build(n - 1, address(s), s with n)
+            };
+            build(100, null, {0}) == {0..100};",
+        );
+        assert_eq!(build, Some(True.wrap()));
+        // Unless something after it still needs the old value
+        let shared = run("f = 0; f = func(n, s, old) { if n == 0 ? [s, old] : f(n - 1, s with n, s) }; f(1, {}, null);");
+        assert_eq!(shared.unwrap().to_string(), "[{1}, {}]");
+    }
+
+    #[test]
+    fn callbacks() {
+        let run = |input| format!("{:?}", run_with_natives(input, VmLimits::default()).unwrap().unwrap());
+        assert_eq!(run("map(func(x) { x * 2 }, [1, 2, 3]);"), "tup([int(2), int(4), int(6)])");
+        assert_eq!(run("map(sum, [1, 2]);"), "tup([int(1), int(2)])");
+
+        // Natives and YSETL functions calling each other, several levels deep
+        let nested = "outer = func(t) { map(func(x, t!) { map(func(y, x!) { sum(x, y) }, t) }, t) };
+            [outer([1, 2]), 10];";
+        assert_eq!(run(nested), "tup([tup([tup([int(2), int(3)]), tup([int(3), int(4)])]), int(10)])");
+
+        // Callbacks can make tail calls of their own
+        let tail = "count = 0; count = func(n, acc) { if n == 0 ? acc : count(n - 1, acc + 1) };
+            map(func(n) { count(n, 0) }, [3, 5000]);";
+        assert_eq!(run(tail), "tup([int(3), int(5000)])");
+
+        // A failed callback leaves the VM as it was, so a native can recover from it
+        let limits = VmLimits { max_frames: Some(50), ..VmLimits::default() };
+        let deep = "deep = 0; deep = func(n) { if n == 0 ? 0 : 1 + deep(n - 1) };";
+        let recovered = run_with_natives(&format!("{deep} f = func(n) {{ [try(func(n!) {{ deep(n) }}), n] }}; f(100);"), limits);
+        assert_eq!(format!("{:?}", recovered.unwrap().unwrap()), "tup([null, int(100)])");
+        assert_eq!(
+            run_with_natives(&format!("{deep} map(deep, [10, 100]);"), limits),
+            Err(RuntimeError::Limit(LimitExceeded { limit: Limit::Frames, max: 50 }))
+        );
+    }
 }